@@ -1,9 +1,17 @@
 use super::errors::ArgumentError;
+use super::filter::{FilterAction, KeyFilter, KeyFilters, KeyPattern};
+use super::inventory::InventoryLocation;
+use super::multipart_copy::{MAX_COPY_OBJECT_SIZE, MIN_PART_SIZE};
+use super::report::ReportFormat;
+use chrono::{DateTime, NaiveDate, Utc};
 use core::str::FromStr;
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 use regex::Regex;
 use sedregex::ReplaceCommand;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(Debug, FromPrimitive, Clone, Copy)]
@@ -47,9 +55,39 @@ impl FromStr for CannedACL {
     }
 }
 
-impl ToString for CannedACL {
-    fn to_string(&self) -> String {
-        String::from(CannedACL::possible_strings()[*self as usize])
+impl fmt::Display for CannedACL {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", CannedACL::possible_strings()[*self as usize])
+    }
+}
+
+/// Format of log messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Timestamp, target and level followed by the message
+    Text,
+    /// One JSON object per line, with fields such as the key and new key of each message
+    Json,
+}
+
+impl LogFormat {
+    pub fn possible_strings() -> &'static [&'static str] {
+        &["text", "json"][..]
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = ArgumentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(ArgumentError::InvalidLogFormat {
+                s: String::from(s),
+                possible_strings: LogFormat::possible_strings(),
+            }),
+        }
     }
 }
 
@@ -83,8 +121,137 @@ pub struct S3Prefix {
     pub key_prefix: Option<String>,
 }
 
+fn parse_endpoint_url(src: &str) -> Result<String, ArgumentError> {
+    if src.starts_with("http://") || src.starts_with("https://") {
+        Ok(String::from(src))
+    } else {
+        Err(ArgumentError::InvalidEndpointUrl {
+            url: String::from(src),
+        })
+    }
+}
+
+/// Parse a size in bytes, with an optional binary unit suffix (e.g. 512MiB, 5G)
+pub fn parse_byte_size(src: &str) -> Result<i64, ArgumentError> {
+    lazy_static! {
+        static ref SIZE_REGEX: Regex =
+            Regex::new(r"^\s*([0-9]+)\s*(?i:(B|K|KB|KIB|M|MB|MIB|G|GB|GIB|T|TB|TIB)?)\s*$")
+                .unwrap();
+    }
+    let invalid = || ArgumentError::InvalidByteSize {
+        size: String::from(src),
+    };
+
+    let captures = SIZE_REGEX.captures(src).ok_or_else(invalid)?;
+    let value: i64 = captures[1].parse().map_err(|_| invalid())?;
+    let multiplier: i64 = match captures.get(2).map(|x| x.as_str().to_uppercase()) {
+        None => 1,
+        Some(unit) => match unit.chars().next() {
+            Some('K') => 1 << 10,
+            Some('M') => 1 << 20,
+            Some('G') => 1 << 30,
+            Some('T') => 1 << 40,
+            _ => 1,
+        },
+    };
+    value.checked_mul(multiplier).ok_or_else(invalid)
+}
+
+fn parse_multipart_threshold(src: &str) -> Result<i64, ArgumentError> {
+    let size = parse_byte_size(src)?;
+    if size > MAX_COPY_OBJECT_SIZE {
+        return Err(ArgumentError::MultipartThresholdTooLarge { size });
+    }
+    Ok(size)
+}
+
+fn parse_multipart_part_size(src: &str) -> Result<i64, ArgumentError> {
+    let size = parse_byte_size(src)?;
+    if !(MIN_PART_SIZE..=MAX_COPY_OBJECT_SIZE).contains(&size) {
+        return Err(ArgumentError::InvalidPartSize { size });
+    }
+    Ok(size)
+}
+
+fn parse_job_count(src: &str) -> Result<usize, ArgumentError> {
+    match src.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(ArgumentError::InvalidJobCount {
+            count: String::from(src),
+        }),
+    }
+}
+
+fn parse_attempt_count(src: &str) -> Result<u32, ArgumentError> {
+    match src.parse::<u32>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(ArgumentError::InvalidAttemptCount {
+            count: String::from(src),
+        }),
+    }
+}
+
+/// Parse a duration with a unit (ms, s, m or h), e.g. 100ms, 20s
+pub fn parse_duration(src: &str) -> Result<Duration, ArgumentError> {
+    lazy_static! {
+        static ref DURATION_REGEX: Regex =
+            Regex::new(r"^\s*([0-9]+)\s*(?i:(ms|s|m|h))\s*$").unwrap();
+    }
+    let invalid = || ArgumentError::InvalidDuration {
+        duration: String::from(src),
+    };
+
+    let captures = DURATION_REGEX.captures(src).ok_or_else(invalid)?;
+    let value: u64 = captures[1].parse().map_err(|_| invalid())?;
+    let millis = match captures[2].to_lowercase().as_str() {
+        "ms" => Some(value),
+        "s" => value.checked_mul(1000),
+        "m" => value.checked_mul(60 * 1000),
+        _ => value.checked_mul(60 * 60 * 1000),
+    };
+    millis.map(Duration::from_millis).ok_or_else(invalid)
+}
+
+/// Parse a time as a date (midnight UTC) or an RFC 3339 time, e.g. 2021-03-14 or
+/// 2021-03-14T12:00:00Z
+pub fn parse_time(src: &str) -> Result<DateTime<Utc>, ArgumentError> {
+    if let Ok(date) = NaiveDate::parse_from_str(src.trim(), "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+    DateTime::parse_from_rfc3339(src.trim())
+        .map(|x| x.with_timezone(&Utc))
+        .map_err(|_| ArgumentError::InvalidTime {
+            time: String::from(src),
+        })
+}
+
+/// Parse a key=value pair, e.g. for --tag team=analytics (the value may contain "=")
+fn parse_key_value(src: &str) -> Result<(String, String), ArgumentError> {
+    match src.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((String::from(key), String::from(value))),
+        _ => Err(ArgumentError::InvalidKeyValue {
+            s: String::from(src),
+        }),
+    }
+}
+
+fn parse_inventory_location(src: &str) -> Result<InventoryLocation, ArgumentError> {
+    if !src.starts_with("s3://") {
+        return Ok(InventoryLocation::Local(PathBuf::from(src)));
+    }
+    match parse_s3_prefix_url(src)? {
+        S3Prefix {
+            bucket,
+            key_prefix: Some(key),
+        } => Ok(InventoryLocation::S3 { bucket, key }),
+        _ => Err(ArgumentError::InvalidS3Url {
+            url: String::from(src),
+        }),
+    }
+}
+
 fn replace_command_from_str(s: &str) -> Result<String, sedregex::ErrorKind> {
-    let r = ReplaceCommand::new(&s);
+    let r = ReplaceCommand::new(s);
     r.map(|_| String::from(s))
 }
 
@@ -102,6 +269,20 @@ pub struct App {
     #[structopt(short, long)]
     pub quiet: bool,
 
+    /// Do not display a progress bar (it is only displayed when stdout is a terminal)
+    #[structopt(long)]
+    pub no_progress: bool,
+
+    /// Format of log messages: text, or one JSON object per line with fields such as key,
+    /// new_key, action and error - warnings and errors are written to stderr, other messages to
+    /// stdout
+    #[structopt(long, default_value = "text", possible_values = LogFormat::possible_strings(), parse(try_from_str = LogFormat::from_str))]
+    pub log_format: LogFormat,
+
+    /// Also append every log message to this file, in the format given by --log-format
+    #[structopt(long, parse(from_os_str))]
+    pub log_file: Option<PathBuf>,
+
     /// Do not carry out modifications (only print)
     #[structopt(short = "n", long)]
     pub dry_run: bool,
@@ -111,18 +292,24 @@ pub struct App {
     #[structopt(long)]
     pub no_preserve_properties: bool,
 
-    /// Perl RegEx Replace Expression (only s/target/replacement/flags form supported)
+    /// Perl RegEx Replace Expression (only s/target/replacement/flags form supported) - required
+    /// unless a subcommand is used
     #[structopt(parse(try_from_str = replace_command_from_str))]
-    pub expr: String,
+    pub expr: Option<String>,
 
-    /// S3 URL: s3://bucket-name/optional-key-prefix
+    /// S3 URL: s3://bucket-name/optional-key-prefix - required unless a subcommand is used
     #[structopt(parse(try_from_str = parse_s3_prefix_url))]
-    pub s3_url: S3Prefix,
+    pub s3_url: Option<S3Prefix>,
 
     /// AWS Region (will be taken from bucket region if not overridden here)
     #[structopt(long, parse(try_from_str = rusoto_core::Region::from_str))]
     pub aws_region: Option<rusoto_core::Region>,
 
+    /// Custom S3 endpoint URL for S3-compatible stores (e.g. http://localhost:9000 for MinIO) -
+    /// the bucket region lookup is skipped, and requests use path-style addressing
+    #[structopt(long, parse(try_from_str = parse_endpoint_url))]
+    pub endpoint_url: Option<String>,
+
     /// Canned access_control_list override - sets this ACL for all renamed keys
     #[structopt(long, possible_values = CannedACL::possible_strings(), parse(try_from_str = CannedACL::from_str))]
     pub canned_acl: Option<CannedACL>,
@@ -139,4 +326,280 @@ pub struct App {
     /// Skip keys that would result in an overwrite
     #[structopt(long)]
     pub no_overwrite: bool,
+
+    /// Objects larger than this are copied with a multipart upload (accepts units, e.g. 1GiB,
+    /// maximum 5GiB)
+    #[structopt(long, default_value = "5GiB", parse(try_from_str = parse_multipart_threshold))]
+    pub multipart_threshold: i64,
+
+    /// Part size for multipart copies (between 5MiB and 5GiB, increased if an object would need
+    /// more than 10,000 parts)
+    #[structopt(long, default_value = "512MiB", parse(try_from_str = parse_multipart_part_size))]
+    pub multipart_part_size: i64,
+
+    /// Number of parts copied in parallel for each multipart copy
+    #[structopt(long, default_value = "8")]
+    pub multipart_parallelism: usize,
+
+    /// Maximum number of keys being renamed at once
+    #[structopt(short, long, default_value = "64", parse(try_from_str = parse_job_count))]
+    pub jobs: usize,
+
+    /// Maximum number of source keys being deleted at once
+    #[structopt(long, default_value = "64", parse(try_from_str = parse_job_count))]
+    pub delete_jobs: usize,
+
+    /// Read the keys to rename from this S3 Inventory report instead of listing the bucket - the
+    /// manifest.json of a CSV or Parquet report, as a local path or S3 URL (not used by apply or
+    /// undo)
+    #[structopt(long, parse(try_from_str = parse_inventory_location))]
+    pub inventory: Option<InventoryLocation>,
+
+    /// Apply the expression only to the keys in this file (one per line, "-" reads from stdin)
+    /// instead of listing the bucket - keys outside the S3 URL's prefix are ignored (not used by
+    /// apply or undo)
+    #[structopt(long, parse(from_os_str), conflicts_with = "inventory")]
+    pub keys_from: Option<PathBuf>,
+
+    /// Only rename keys matching this pattern - a glob (where * also matches "/") or, prefixed
+    /// with "regex:", a regular expression. Can be repeated and combined with --exclude: the last
+    /// filter matching a key decides, and if the first filter is --include, keys matching no
+    /// filter are excluded (not used by apply, map or undo)
+    #[structopt(long, number_of_values = 1)]
+    pub include: Vec<KeyPattern>,
+
+    /// Do not rename keys matching this pattern (see --include)
+    #[structopt(long, number_of_values = 1)]
+    pub exclude: Vec<KeyPattern>,
+
+    /// Only rename objects last modified before this time - a date at midnight UTC (e.g.
+    /// 2021-03-14), or an RFC 3339 time (e.g. 2021-03-14T12:00:00Z) (not used by apply, map or
+    /// undo)
+    #[structopt(long, parse(try_from_str = parse_time))]
+    pub modified_before: Option<DateTime<Utc>>,
+
+    /// Only rename objects last modified at or after this time (see --modified-before)
+    #[structopt(long, parse(try_from_str = parse_time))]
+    pub modified_after: Option<DateTime<Utc>>,
+
+    /// Only rename objects of at least this size, accepts units e.g. 1MiB (not used by apply, map
+    /// or undo)
+    #[structopt(long, parse(try_from_str = parse_byte_size))]
+    pub min_size: Option<i64>,
+
+    /// Only rename objects of at most this size, accepts units e.g. 1GiB (not used by apply, map
+    /// or undo)
+    #[structopt(long, parse(try_from_str = parse_byte_size))]
+    pub max_size: Option<i64>,
+
+    /// Only rename objects in this storage class (e.g. STANDARD, GLACIER), can be repeated (not
+    /// used by apply, map or undo)
+    #[structopt(long, number_of_values = 1)]
+    pub storage_class: Vec<String>,
+
+    /// Only rename objects with this tag, given as key=value - can be repeated, and every tag
+    /// must match. Fetches the tags of each object (not used by apply, map or undo)
+    #[structopt(long, number_of_values = 1, parse(try_from_str = parse_key_value))]
+    pub tag: Vec<(String, String)>,
+
+    /// Only rename objects with this user-defined metadata, given as key=value without the
+    /// x-amz-meta- prefix - can be repeated, and every entry must match (not used by apply, map
+    /// or undo)
+    #[structopt(long, number_of_values = 1, parse(try_from_str = parse_key_value))]
+    pub metadata: Vec<(String, String)>,
+
+    /// Only rename objects with this Content-Type (e.g. image/jpeg), can be repeated (not used by
+    /// apply, map or undo)
+    #[structopt(long, number_of_values = 1)]
+    pub content_type: Vec<String>,
+
+    /// The --include and --exclude filters in the order given, set by `App::parse`
+    #[structopt(skip)]
+    pub filters: KeyFilters,
+
+    /// Start renaming keys as each page of keys is listed, rather than listing every key first -
+    /// uses less memory on large prefixes, but collisions and chains of renames (e.g. a -> b
+    /// while b -> c) are only detected within each page of 1000 keys
+    #[structopt(long)]
+    pub stream: bool,
+
+    /// Also rename directory markers (keys ending in "/", e.g. folders created in the S3 console)
+    /// along with the keys under them - by default they are skipped (not used by apply, map or
+    /// undo)
+    #[structopt(long)]
+    pub directory_markers: bool,
+
+    /// After renaming, delete the directory markers containing renamed keys which are left with
+    /// nothing under them
+    #[structopt(long)]
+    pub remove_empty_markers: bool,
+
+    /// List the prefix with a "/" delimiter this many levels deep, then list the keys under each
+    /// common prefix found concurrently - much faster for large prefixes split into many
+    /// sub-prefixes (e.g. by date), 0 lists every key in sequence
+    #[structopt(long, default_value = "0")]
+    pub list_depth: usize,
+
+    /// Maximum number of prefixes being listed at once with --list-depth
+    #[structopt(long, default_value = "16", parse(try_from_str = parse_job_count))]
+    pub list_jobs: usize,
+
+    /// Maximum number of attempts for each S3 request, requests failing with throttling, server
+    /// or network errors are retried (1 disables retries)
+    #[structopt(long, default_value = "5", parse(try_from_str = parse_attempt_count))]
+    pub retry_attempts: u32,
+
+    /// Delay before the first retry of a request, doubled for each further retry and randomised
+    /// (accepts units ms, s, m or h)
+    #[structopt(long, default_value = "100ms", parse(try_from_str = parse_duration))]
+    pub retry_base_delay: Duration,
+
+    /// Longest delay between retries of a request (accepts units ms, s, m or h)
+    #[structopt(long, default_value = "20s", parse(try_from_str = parse_duration))]
+    pub retry_max_delay: Duration,
+
+    /// Record the progress of the run to this file, so that it can be continued with --resume if
+    /// it is interrupted (the file is overwritten)
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["dry-run", "resume"])]
+    pub journal: Option<PathBuf>,
+
+    /// Resume the run recorded in this journal file: keys already renamed are skipped, and keys
+    /// already copied have their source deleted without being copied again - the expression and
+    /// S3 URL must match the original run
+    #[structopt(long, parse(from_os_str), conflicts_with = "dry-run")]
+    pub resume: Option<PathBuf>,
+
+    /// Record every completed rename (old key, new key, ETag and version ID) to this file, so
+    /// that the renames can be reversed with the undo subcommand (the file is overwritten)
+    #[structopt(long, parse(from_os_str), conflicts_with = "dry-run")]
+    pub manifest: Option<PathBuf>,
+
+    /// Write the outcome of every key (source, target, action, error, size, storage class and
+    /// timing) to this file as it finishes, in the format given by --report-format (the file is
+    /// overwritten)
+    #[structopt(long, parse(from_os_str))]
+    pub report: Option<PathBuf>,
+
+    /// Format of the report written with --report: one JSON object per line, or CSV with a
+    /// header row
+    #[structopt(long, default_value = "jsonl", possible_values = ReportFormat::possible_strings(), parse(try_from_str = ReportFormat::from_str))]
+    pub report_format: ReportFormat,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Compute the renames without carrying them out, and write them with the options used to a
+    /// JSON plan file for review (options such as --no-preserve-acl must be given before the
+    /// subcommand)
+    Plan {
+        /// File to write the plan to
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+
+        /// Perl RegEx Replace Expression (only s/target/replacement/flags form supported)
+        #[structopt(parse(try_from_str = replace_command_from_str))]
+        expr: String,
+
+        /// S3 URL: s3://bucket-name/optional-key-prefix
+        #[structopt(parse(try_from_str = parse_s3_prefix_url))]
+        s3_url: S3Prefix,
+    },
+    /// Carry out exactly the renames in a plan file written by the plan subcommand, with the
+    /// options recorded in it - refuses to run if any source key changed since the plan was made
+    Apply {
+        /// Plan file written by the plan subcommand
+        #[structopt(parse(from_os_str))]
+        plan: PathBuf,
+    },
+    /// Rename each source key to its target as listed in a mapping file, rather than with an
+    /// expression: .csv or .tsv files of source,target pairs, or .jsonl files of
+    /// {"source": ..., "target": ...} objects (options such as --no-overwrite must be given before
+    /// the subcommand)
+    Map {
+        /// Mapping file of source and target keys
+        #[structopt(parse(from_os_str))]
+        mapping: PathBuf,
+
+        /// S3 URL of the bucket: s3://bucket-name (keys in the mapping are not relative to any
+        /// prefix)
+        #[structopt(parse(try_from_str = parse_s3_prefix_url))]
+        s3_url: S3Prefix,
+    },
+    /// Reverse the renames recorded in a manifest written with --manifest - each key is only moved
+    /// back if it is unchanged, and never overwrites an existing key (other options such as
+    /// --dry-run and --no-preserve-acl must be given before the subcommand)
+    Undo {
+        /// Manifest file written with --manifest
+        #[structopt(parse(from_os_str))]
+        manifest: PathBuf,
+    },
+}
+
+impl App {
+    /// Parse the command line arguments, exiting with a usage error if they are invalid
+    ///
+    /// The expression and S3 URL are optional to `StructOpt` so that subcommands can be used
+    /// without them, so are checked here.
+    pub fn parse() -> Self {
+        let matches = App::clap().get_matches();
+        let mut app = App::from_clap(&matches);
+        app.filters = app.ordered_filters(&matches);
+        if app.command.is_none() && (app.expr.is_none() || app.s3_url.is_none()) {
+            clap::Error::with_description(
+                "The <expr> and <s3-url> arguments are required unless a subcommand is used",
+                clap::ErrorKind::MissingRequiredArgument,
+            )
+            .exit();
+        }
+        if let Some(message) = app.subcommand_conflict() {
+            clap::Error::with_description(&message, clap::ErrorKind::ArgumentConflict).exit();
+        }
+        app
+    }
+
+    /// Describe an option given which the subcommand does not use, if any (clap cannot make
+    /// options conflict with subcommands)
+    fn subcommand_conflict(&self) -> Option<String> {
+        let command = match &self.command {
+            None => return None,
+            Some(Command::Plan { .. }) => "plan",
+            Some(Command::Apply { .. }) => "apply",
+            Some(Command::Map { .. }) => "map",
+            Some(Command::Undo { .. }) => "undo",
+        };
+        // Only plain renames are recorded to a journal, so only they can be resumed, and a plan
+        // renames nothing to report or record
+        let plan = command == "plan";
+        let unused = [
+            ("--journal", self.journal.is_some()),
+            ("--resume", self.resume.is_some()),
+            ("--report", plan && self.report.is_some()),
+            ("--manifest", plan && self.manifest.is_some()),
+        ];
+        let (option, _) = unused.iter().find(|(_, given)| *given)?;
+        Some(format!(
+            "The argument '{}' cannot be used with the {} subcommand",
+            option, command
+        ))
+    }
+
+    /// The --include and --exclude filters, in the order they were given
+    fn ordered_filters(&self, matches: &clap::ArgMatches) -> KeyFilters {
+        let indexed = |name: &str, action: FilterAction, patterns: &[KeyPattern]| {
+            matches
+                .indices_of(name)
+                .into_iter()
+                .flatten()
+                .zip(patterns.iter().cloned())
+                .map(move |(index, pattern)| (index, KeyFilter { action, pattern }))
+                .collect::<Vec<_>>()
+        };
+        let mut filters = indexed("include", FilterAction::Include, &self.include);
+        filters.extend(indexed("exclude", FilterAction::Exclude, &self.exclude));
+        filters.sort_by_key(|(index, _)| *index);
+        KeyFilters::new(filters.into_iter().map(|(_, filter)| filter).collect())
+    }
 }
@@ -0,0 +1,64 @@
+use super::errors::ArgumentError;
+use core::str::FromStr;
+use log::debug;
+use rusoto_core::Region;
+use rusoto_s3::{GetBucketLocationRequest, S3Client, S3};
+
+/// Options for connecting to S3 (or an S3-compatible store)
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionOptions {
+    /// AWS Region, taken from the bucket location if not set
+    pub aws_region: Option<Region>,
+    /// Custom endpoint URL for S3-compatible stores (e.g. MinIO, Ceph RGW, LocalStack)
+    pub endpoint_url: Option<String>,
+}
+
+/// Create an S3Client for the region containing `bucket`
+///
+/// The region is taken from `aws_region` if given, otherwise it is requested from the bucket
+/// location.
+///
+/// If an endpoint URL is given, the bucket location is not requested (S3-compatible stores rarely
+/// return anything useful) and `aws_region` (default us-east-1) is only used for signing. Note
+/// that requests are always sent with path-style addressing (i.e. `endpoint/bucket/key`), as
+/// required by most S3-compatible stores.
+pub async fn client_for_bucket(
+    connection: &ConnectionOptions,
+    bucket: &str,
+) -> Result<S3Client, anyhow::Error> {
+    let aws_region = connection.aws_region.clone();
+    if let Some(endpoint) = &connection.endpoint_url {
+        let region = Region::Custom {
+            name: String::from(aws_region.unwrap_or_default().name()),
+            endpoint: String::from(endpoint.trim_end_matches('/')),
+        };
+        debug!("{:?}", region);
+        return Ok(S3Client::new(region));
+    }
+
+    let client = S3Client::new(aws_region.clone().unwrap_or_default());
+
+    let bucket_region: Option<Region> = match client
+        .get_bucket_location(GetBucketLocationRequest {
+            bucket: String::from(bucket),
+        })
+        .await?
+        .location_constraint
+        .map(|x| Region::from_str(&x))
+    {
+        None => None,
+        Some(Err(_err)) => None, // Note we ignore failure to get bucket region
+        Some(Ok(aws_region)) => Some(aws_region),
+    };
+
+    let target_region = match (aws_region, bucket_region) {
+        (Some(aws_region), _) => Ok(aws_region),
+        (None, Some(bucket_region)) => Ok(bucket_region),
+        (None, None) => Err(ArgumentError::CouldNotDetermineBucketRegion {
+            bucket: String::from(bucket), // TODO: try fallback to AWS config here?
+        }),
+    }?;
+
+    debug!("{:?}", target_region);
+    Ok(S3Client::new(target_region))
+}
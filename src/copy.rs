@@ -0,0 +1,115 @@
+use super::errors::RequestError;
+use super::multipart_copy::{multipart_copy, MultipartOptions};
+use super::storage::Storage;
+use anyhow::anyhow;
+use log::debug;
+use rusoto_core::RusotoError;
+use rusoto_s3::HeadObjectRequest;
+use rusoto_s3::{CopyObjectRequest, DeleteObjectRequest, HeadObjectError, HeadObjectOutput};
+
+/// Copy the object, using a multipart copy if `size` is above the multipart threshold
+pub async fn copy_object(
+    client: &dyn Storage,
+    request: CopyObjectRequest,
+    size: Option<i64>,
+    multipart: &MultipartOptions,
+) -> Result<(), anyhow::Error> {
+    match size {
+        Some(size) if size > multipart.threshold => {
+            multipart_copy(client, request, size, multipart).await
+        }
+        _ => {
+            client
+                .copy_object(request)
+                .await
+                .map_err(RequestError::from)?;
+            Ok(())
+        }
+    }
+}
+
+/// Check that the copied object exists at `key`, with the expected size if known, returning its
+/// properties
+pub async fn verify_copy(
+    client: &dyn Storage,
+    bucket: &str,
+    key: &str,
+    size: Option<i64>,
+) -> Result<HeadObjectOutput, anyhow::Error> {
+    let head_result = client
+        .head_object(HeadObjectRequest {
+            bucket: String::from(bucket),
+            if_match: None,
+            if_modified_since: None,
+            if_none_match: None,
+            if_unmodified_since: None,
+            key: String::from(key),
+            part_number: None,
+            range: None,
+            request_payer: None,
+            sse_customer_algorithm: None,
+            sse_customer_key: None,
+            sse_customer_key_md5: None,
+            version_id: None,
+        })
+        .await
+        .map_err(|err| RequestError::new(format!("Could not verify copy at {}", key), err))?;
+
+    match (size, head_result.content_length) {
+        (Some(expected), Some(actual)) if expected != actual => Err(anyhow!(
+            "Copy at {} has size {} bytes, expected {} bytes",
+            key,
+            actual,
+            expected
+        )),
+        _ => {
+            debug!("Verified {}", key);
+            Ok(head_result)
+        }
+    }
+}
+
+/// Whether an object exists at `key`
+pub async fn key_exists(
+    client: &dyn Storage,
+    bucket: &str,
+    key: &str,
+) -> Result<bool, anyhow::Error> {
+    let head_request = HeadObjectRequest {
+        bucket: String::from(bucket),
+        key: String::from(key),
+        ..Default::default()
+    };
+    match client.head_object(head_request).await {
+        Ok(_) => Ok(true),
+        Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => Ok(false),
+        // HEAD responses have no body, so a missing key is usually only a bare 404
+        Err(RusotoError::Unknown(response)) if response.status == http::StatusCode::NOT_FOUND => {
+            Ok(false)
+        }
+        Err(err) => {
+            Err(RequestError::new(format!("Could not check whether {} exists", key), err).into())
+        }
+    }
+}
+
+/// Delete the source key once its copy has been verified
+pub async fn delete_source(
+    client: &dyn Storage,
+    bucket: &str,
+    key: &str,
+) -> Result<(), anyhow::Error> {
+    client
+        .delete_object(DeleteObjectRequest {
+            bucket: String::from(bucket),
+            bypass_governance_retention: None, // TODO: For Object Lock
+            key: String::from(key),
+            mfa: None, // TODO: Required to permanently delete if MFA and versioning enabled
+            request_payer: None,
+            version_id: None,
+        })
+        .await
+        .map_err(|err| RequestError::new("Failed to delete source key", err))?;
+    debug!("Deleted {}", key);
+    Ok(())
+}
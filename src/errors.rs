@@ -1,4 +1,7 @@
+use rusoto_core::RusotoError;
 use rusoto_s3::Grantee;
+use std::fmt;
+use std::path::PathBuf;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -7,6 +10,60 @@ pub enum S3Error {
     EmptyBucket { bucket: String, prefix: String },
 }
 
+/// A failed S3 request, keeping the ID of the request (if the response had one) so that it can be
+/// logged with the failure, for reporting to AWS support
+#[derive(Error, Debug)]
+#[error("{message}")]
+pub struct RequestError {
+    message: String,
+    request_id: Option<String>,
+}
+
+impl RequestError {
+    /// The error of a failed request, described as `context` followed by the error
+    pub fn new<E: std::error::Error + 'static>(
+        context: impl fmt::Display,
+        error: RusotoError<E>,
+    ) -> Self {
+        RequestError {
+            message: format!("{}: {}", context, error),
+            request_id: request_id(&error).map(String::from),
+        }
+    }
+
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+}
+
+impl<E: std::error::Error + 'static> From<RusotoError<E>> for RequestError {
+    fn from(error: RusotoError<E>) -> Self {
+        RequestError {
+            message: error.to_string(),
+            request_id: request_id(&error).map(String::from),
+        }
+    }
+}
+
+/// ID of the failed request, from the x-amz-request-id header of the response (if there was
+/// one), for reporting to AWS support
+pub(crate) fn request_id<E>(error: &RusotoError<E>) -> Option<&str> {
+    match error {
+        RusotoError::Unknown(response) => {
+            response.headers.get("x-amz-request-id").map(|x| x.as_str())
+        }
+        _ => None,
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum RenameError {
+    #[error("Refusing to rename, {count} target keys would be written by more than one key, including: {examples:?}")]
+    TargetCollisions { count: usize, examples: Vec<String> },
+    #[error("Renaming a sequence of keys stopped unexpectedly: {error}")]
+    TaskFailed { error: tokio::task::JoinError },
+}
+
 #[derive(Error, Debug)]
 pub enum ExpressionError {
     #[error("Could not parse expression: {expression}, error: {error:?}")]
@@ -20,8 +77,40 @@ pub enum ExpressionError {
 pub enum ArgumentError {
     #[error("Invalid S3 URL: {url:?}, expected format: s3://bucket/optional-key-prefix")]
     InvalidS3Url { url: String },
+    #[error("Invalid endpoint URL: {url:?}, expected format: http(s)://host[:port]")]
+    InvalidEndpointUrl { url: String },
     #[error("Could not determine bucket region for S3 bucket: s3://{bucket:?}, please specify with --aws-region")]
     CouldNotDetermineBucketRegion { bucket: String },
+    #[error(
+        "Invalid size: {size:?}, expected a number of bytes with an optional unit, e.g. 512MiB"
+    )]
+    InvalidByteSize { size: String },
+    #[error("Multipart threshold of {size} bytes is larger than the 5GiB limit for CopyObject")]
+    MultipartThresholdTooLarge { size: i64 },
+    #[error("Invalid multipart part size: {size} bytes, must be between 5MiB and 5GiB")]
+    InvalidPartSize { size: i64 },
+    #[error("Invalid number of jobs: {count:?}, must be a whole number greater than 0")]
+    InvalidJobCount { count: String },
+    #[error("Invalid duration: {duration:?}, expected a number with a unit, e.g. 100ms, 5s or 1m")]
+    InvalidDuration { duration: String },
+    #[error("Invalid number of attempts: {count:?}, must be a whole number greater than 0")]
+    InvalidAttemptCount { count: String },
+    #[error("Invalid time: {time:?}, expected a date (e.g. 2021-03-14) or an RFC 3339 time (e.g. 2021-03-14T12:00:00Z)")]
+    InvalidTime { time: String },
+    #[error("Invalid {s:?}, expected key=value")]
+    InvalidKeyValue { s: String },
+    #[error("Invalid key pattern: {pattern:?}, {error}")]
+    InvalidKeyPattern { pattern: String, error: String },
+    #[error("Invalid log format: {s}, must be in {possible_strings:?}")]
+    InvalidLogFormat {
+        s: String,
+        possible_strings: &'static [&'static str],
+    },
+    #[error("Invalid report format: {s}, must be in {possible_strings:?}")]
+    InvalidReportFormat {
+        s: String,
+        possible_strings: &'static [&'static str],
+    },
     #[error("Invalid Canned ACL string provided: {s}, must be in {possible_strings:?}")]
     InvalidCannedACL {
         s: String,
@@ -29,15 +118,130 @@ pub enum ArgumentError {
     },
 }
 
+#[derive(Error, Debug)]
+pub enum JournalError {
+    #[error("Could not access journal {path:?}: {error}")]
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    #[error("Could not parse line {line} of journal {path:?}: {error}")]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        error: serde_json::Error,
+    },
+    #[error(
+        "Journal {path:?} was not written by a run with the same bucket, prefix and expression"
+    )]
+    OptionsMismatch { path: PathBuf },
+}
+
+#[derive(Error, Debug)]
+pub enum ManifestError {
+    #[error("Could not access manifest {path:?}: {error}")]
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    #[error("Could not parse line {line} of manifest {path:?}: {error}")]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        error: serde_json::Error,
+    },
+    #[error("Manifest {path:?} does not contain any renames")]
+    Empty { path: PathBuf },
+    #[error("Manifest {path:?} contains renames in more than one bucket")]
+    MultipleBuckets { path: PathBuf },
+}
+
+#[derive(Error, Debug)]
+pub enum ReportError {
+    #[error("Could not create report {path:?}: {error}")]
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum PlanError {
+    #[error("Could not access plan {path:?}: {error}")]
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    #[error("Could not parse plan {path:?}: {error}")]
+    Parse {
+        path: PathBuf,
+        error: serde_json::Error,
+    },
+    #[error("Invalid canned ACL in plan: {0}")]
+    InvalidCannedACL(ArgumentError),
+    #[error("Refusing to apply plan, {count} source keys changed since it was made, including: {examples:?}")]
+    SourcesChanged { count: usize, examples: Vec<String> },
+}
+
+#[derive(Error, Debug)]
+pub enum InventoryError {
+    #[error("Could not read inventory file {location}: {error}")]
+    Read { location: String, error: String },
+    #[error("Could not parse inventory manifest {location}: {error}")]
+    InvalidManifest {
+        location: String,
+        error: serde_json::Error,
+    },
+    #[error("Could not parse inventory data file {location}: {error}")]
+    InvalidDataFile { location: String, error: String },
+    #[error("Unsupported inventory format: {format}, only CSV and Parquet reports can be read")]
+    UnsupportedFormat { format: String },
+    #[error(
+        "ORC inventory reports cannot be read, configure the inventory to write CSV or Parquet"
+    )]
+    OrcUnsupported,
+    #[error("Inventory lists keys of bucket {inventory_bucket}, not {bucket}")]
+    BucketMismatch {
+        inventory_bucket: String,
+        bucket: String,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum MappingError {
+    #[error("Could not read mapping file {path:?}: {error}")]
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    #[error("Could not parse line {line} of mapping file {path:?}: {error}")]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        error: String,
+    },
+    #[error("Unknown format of mapping file {path:?}, expected a .csv, .tsv or .jsonl file")]
+    UnknownFormat { path: PathBuf },
+}
+
+#[derive(Error, Debug)]
+pub enum KeysFromError {
+    #[error("Could not read keys from {path:?}: {error}")]
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+}
+
 #[derive(Error, Debug)]
 pub enum GranteeParseError {
     #[error("Could not get valid ID for grantee: {grantee:?}")]
-    NoValidID { grantee: Grantee },
+    NoValidID { grantee: Box<Grantee> },
     #[error("Invalid permission type: {permission} for grantee: {grantee:?}")]
     InvalidPermission {
         permission: String,
-        grantee: Grantee,
+        grantee: Box<Grantee>,
     },
     #[error("Missing permission for grantee: {grantee:?}")]
-    MissingPermission { grantee: Grantee },
+    MissingPermission { grantee: Box<Grantee> },
 }
@@ -0,0 +1,149 @@
+use super::report::Report;
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Reason a key was not renamed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The expression did not change the key
+    Unchanged,
+    /// The target key already exists and overwriting was disabled
+    WouldOverwrite,
+    /// The rename was planned but not carried out due to a dry run
+    DryRun,
+    /// The run was shut down before the key was copied
+    Interrupted,
+    /// The key was created by a rename in the run being resumed
+    AlreadyRenamed,
+    /// The target is another key which is renamed first, and that rename did not complete
+    ChainBroken,
+    /// The object's properties (only known once it was fetched) are excluded by the filters
+    Filtered,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Unchanged => write!(f, "key did not change"),
+            SkipReason::WouldOverwrite => write!(f, "this would result in overwriting"),
+            SkipReason::DryRun => write!(f, "dry run"),
+            SkipReason::Interrupted => write!(f, "interrupted"),
+            SkipReason::AlreadyRenamed => write!(f, "key was created by the resumed run"),
+            SkipReason::ChainBroken => write!(f, "an earlier rename in its chain did not complete"),
+            SkipReason::Filtered => write!(f, "excluded by the filters"),
+        }
+    }
+}
+
+/// Stage of the rename pipeline reached by a key
+///
+/// Keys move through Planned -> Copied -> Verified -> Deleted. Keys at Copied or Verified exist
+/// at both the source and target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RenameStage {
+    /// The target key has been computed but nothing has been modified
+    Planned,
+    /// The object has been copied to the target key
+    Copied,
+    /// The copy has been checked to exist at the target key
+    Verified,
+    /// The source key has been deleted, completing the rename
+    Deleted,
+}
+
+impl fmt::Display for RenameStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenameStage::Planned => write!(f, "planned"),
+            RenameStage::Copied => write!(f, "copied"),
+            RenameStage::Verified => write!(f, "verified"),
+            RenameStage::Deleted => write!(f, "deleted"),
+        }
+    }
+}
+
+/// Progress of a single key through a rename
+#[derive(Debug, Clone, PartialEq)]
+pub enum RenameEvent {
+    /// The key will be renamed from `source` to `target`
+    Planned { source: String, target: String },
+    /// The key will not be renamed
+    Skipped {
+        source: String,
+        target: String,
+        reason: SkipReason,
+    },
+    /// The object has been copied to the target key
+    Copied { source: String, target: String },
+    /// The copy has been checked to exist at the target key
+    Verified { source: String, target: String },
+    /// The source key has been deleted, completing the rename
+    Deleted { source: String, target: String },
+    /// The rename failed after reaching `stage`, `target` is `None` if the failure occurred
+    /// before it was known. `request_id` is the ID of the failed S3 request, if known.
+    Failed {
+        source: String,
+        target: Option<String>,
+        stage: RenameStage,
+        error: String,
+        request_id: Option<String>,
+    },
+}
+
+impl RenameEvent {
+    /// The original key this event refers to
+    pub fn source(&self) -> &str {
+        match self {
+            RenameEvent::Planned { source, .. }
+            | RenameEvent::Skipped { source, .. }
+            | RenameEvent::Copied { source, .. }
+            | RenameEvent::Verified { source, .. }
+            | RenameEvent::Deleted { source, .. }
+            | RenameEvent::Failed { source, .. } => source,
+        }
+    }
+}
+
+/// Sending half of the channel behind the stream returned by `Renamer::run`
+#[derive(Clone)]
+pub(crate) struct EventSender {
+    sender: mpsc::UnboundedSender<Result<RenameEvent, anyhow::Error>>,
+    /// Every event is also passed to the report, if there is one
+    report: Option<Arc<Report>>,
+}
+
+impl EventSender {
+    pub(crate) fn channel() -> (
+        Self,
+        mpsc::UnboundedReceiver<Result<RenameEvent, anyhow::Error>>,
+    ) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (
+            EventSender {
+                sender: tx,
+                report: None,
+            },
+            rx,
+        )
+    }
+
+    /// Record the outcome of each key to `report` as its events are sent
+    pub(crate) fn with_report(mut self, report: Option<Arc<Report>>) -> Self {
+        self.report = report;
+        self
+    }
+
+    /// Send an event, ignoring the case where the stream has been dropped
+    pub(crate) fn send(&self, event: RenameEvent) {
+        if let Some(report) = &self.report {
+            report.update(&event);
+        }
+        let _ = self.sender.send(Ok(event));
+    }
+
+    /// Send an error which ended the run
+    pub(crate) fn send_error(&self, error: anyhow::Error) {
+        let _ = self.sender.send(Err(error));
+    }
+}
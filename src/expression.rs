@@ -0,0 +1,35 @@
+use super::errors::ExpressionError;
+use log::debug;
+use sedregex::ReplaceCommand;
+
+/// Parse a sed-style replace expression
+///
+/// Unless `no_anonymous_groups` is set, `\N` capture group references are converted to `$N`.
+pub fn parse_expression(
+    expr: &str,
+    no_anonymous_groups: bool,
+) -> Result<ReplaceCommand<'static>, ExpressionError> {
+    let parsed_string = if !no_anonymous_groups {
+        // Pre-parse regex to allow \N syntax for capture groups (as well as $N)
+        // This is a heuristic, we do not check if preceding backslash was already escaped
+        // Can be disabled with --no-anonymous-groups flag
+        lazy_static! {
+            static ref CAPTURE_REGEX: regex::Regex =
+                regex::Regex::new("\\\\(?P<index>[0-9])").unwrap();
+        }
+        let parsed_string = CAPTURE_REGEX.replace_all(expr, "$$$index");
+
+        debug!("{}", parsed_string);
+        parsed_string.into_owned()
+    } else {
+        String::from(expr)
+    };
+
+    // We leak Box to get &'static str which is thread-safe and can be put inside ReplaceCommand
+    let static_str: &'static str = Box::leak(parsed_string.into_boxed_str());
+
+    ReplaceCommand::new(static_str).map_err(|err| ExpressionError::SedRegexParseError {
+        expression: String::from(expr),
+        error: err,
+    })
+}
@@ -0,0 +1,205 @@
+use super::errors::ArgumentError;
+use super::listing::SourceObject;
+use chrono::{DateTime, Utc};
+use core::str::FromStr;
+use globset::{Glob, GlobMatcher};
+use regex::Regex;
+use rusoto_s3::{HeadObjectOutput, Tag};
+use std::fmt;
+
+/// A pattern matched against whole keys
+#[derive(Clone)]
+pub enum KeyPattern {
+    /// Shell-style glob, where `*` also matches "/" (e.g. `logs/*.gz`)
+    Glob(GlobMatcher),
+    /// Regular expression, which may match any part of the key unless anchored
+    Regex(Regex),
+}
+
+impl KeyPattern {
+    pub fn is_match(&self, key: &str) -> bool {
+        match self {
+            KeyPattern::Glob(glob) => glob.is_match(key),
+            KeyPattern::Regex(regex) => regex.is_match(key),
+        }
+    }
+}
+
+/// Shows only the pattern, rather than the compiled matcher
+impl fmt::Debug for KeyPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyPattern::Glob(glob) => f.debug_tuple("Glob").field(&glob.glob().glob()).finish(),
+            KeyPattern::Regex(regex) => f.debug_tuple("Regex").field(&regex.as_str()).finish(),
+        }
+    }
+}
+
+/// Patterns starting with "regex:" are regular expressions, and all others are globs
+impl FromStr for KeyPattern {
+    type Err = ArgumentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |error: String| ArgumentError::InvalidKeyPattern {
+            pattern: String::from(s),
+            error,
+        };
+        match s.strip_prefix("regex:") {
+            Some(regex) => Regex::new(regex)
+                .map(KeyPattern::Regex)
+                .map_err(|x| invalid(x.to_string())),
+            None => Glob::new(s)
+                .map(|x| KeyPattern::Glob(x.compile_matcher()))
+                .map_err(|x| invalid(x.to_string())),
+        }
+    }
+}
+
+/// Whether keys matching a filter's pattern are renamed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterAction {
+    Include,
+    Exclude,
+}
+
+#[derive(Debug, Clone)]
+pub struct KeyFilter {
+    pub action: FilterAction,
+    pub pattern: KeyPattern,
+}
+
+/// Include and exclude filters narrowing which keys are renamed, evaluated in order
+///
+/// The last filter matching a key decides whether it is included. Keys matching no filter are
+/// included, unless the first filter is an include filter (e.g. `--include '*.jpg'` alone renames
+/// only .jpg keys).
+#[derive(Debug, Clone, Default)]
+pub struct KeyFilters(Vec<KeyFilter>);
+
+impl KeyFilters {
+    pub fn new(filters: Vec<KeyFilter>) -> Self {
+        KeyFilters(filters)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn includes(&self, key: &str) -> bool {
+        let default = self
+            .0
+            .first()
+            .is_none_or(|x| x.action == FilterAction::Exclude);
+        self.0
+            .iter()
+            .rev()
+            .find(|x| x.pattern.is_match(key))
+            .map_or(default, |x| x.action == FilterAction::Include)
+    }
+}
+
+/// Filters on the properties of objects (rather than their keys), all of which must match
+#[derive(Debug, Clone, Default)]
+pub struct ObjectFilters {
+    /// Only objects last modified before this time
+    pub modified_before: Option<DateTime<Utc>>,
+    /// Only objects last modified at or after this time
+    pub modified_after: Option<DateTime<Utc>>,
+    /// Only objects of at least this many bytes
+    pub min_size: Option<i64>,
+    /// Only objects of at most this many bytes
+    pub max_size: Option<i64>,
+    /// Only objects in any of these storage classes (in upper case, e.g. STANDARD_IA)
+    pub storage_classes: Vec<String>,
+    /// Only objects with all of these tags (key and value)
+    pub tags: Vec<(String, String)>,
+    /// Only objects with all of these user-defined metadata entries (keys without the
+    /// "x-amz-meta-" prefix, compared ignoring case)
+    pub metadata: Vec<(String, String)>,
+    /// Only objects with any of these content types (compared ignoring case and parameters, e.g.
+    /// "; charset=utf-8")
+    pub content_types: Vec<String>,
+}
+
+impl ObjectFilters {
+    pub fn is_empty(&self) -> bool {
+        self.modified_before.is_none()
+            && self.modified_after.is_none()
+            && self.min_size.is_none()
+            && self.max_size.is_none()
+            && self.storage_classes.is_empty()
+            && self.tags.is_empty()
+            && self.metadata.is_empty()
+            && self.content_types.is_empty()
+    }
+
+    /// Whether objects must be fetched with HEAD to check `includes_head`
+    pub(crate) fn needs_head(&self) -> bool {
+        !self.metadata.is_empty() || !self.content_types.is_empty()
+    }
+
+    /// Whether the tags of objects must be fetched to check `includes_tags`
+    pub(crate) fn needs_tags(&self) -> bool {
+        !self.tags.is_empty()
+    }
+
+    /// Whether an object with the properties returned by HEAD matches the metadata and content
+    /// type filters
+    pub(crate) fn includes_head(&self, head: &HeadObjectOutput) -> bool {
+        let media_type = |x: &str| {
+            x.split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_lowercase()
+        };
+        let content_type = head.content_type.as_deref().map(media_type);
+        let metadata = head.metadata.clone().unwrap_or_default();
+        self.metadata.iter().all(|(key, value)| {
+            metadata
+                .iter()
+                .any(|(k, v)| k.eq_ignore_ascii_case(key) && v == value)
+        }) && (self.content_types.is_empty()
+            || self
+                .content_types
+                .iter()
+                .any(|x| content_type == Some(media_type(x))))
+    }
+
+    /// Whether an object with these tags matches the tag filters
+    pub(crate) fn includes_tags(&self, tags: &[Tag]) -> bool {
+        self.tags
+            .iter()
+            .all(|(key, value)| tags.iter().any(|x| &x.key == key && &x.value == value))
+    }
+
+    /// Whether `object` is included, or `None` if a property needed to decide is not known (e.g.
+    /// for keys which were not listed)
+    pub(crate) fn includes(&self, object: &SourceObject) -> Option<bool> {
+        let modified = object.last_modified;
+        let checks = [
+            self.modified_before
+                .map(|before| modified.map(|x| x < before)),
+            self.modified_after
+                .map(|after| modified.map(|x| x >= after)),
+            self.min_size.map(|min| object.size.map(|x| x >= min)),
+            self.max_size.map(|max| object.size.map(|x| x <= max)),
+            Some(&self.storage_classes)
+                .filter(|x| !x.is_empty())
+                .map(|classes| {
+                    object
+                        .storage_class
+                        .as_ref()
+                        .map(|x| classes.contains(&x.to_uppercase()))
+                }),
+        ];
+        let checks = checks.iter().flatten();
+        if checks.clone().any(|x| *x == Some(false)) {
+            Some(false)
+        } else if checks.clone().any(|x| x.is_none()) {
+            None
+        } else {
+            Some(true)
+        }
+    }
+}
@@ -0,0 +1,452 @@
+use super::errors::InventoryError;
+use super::listing::{parse_last_modified, SourceObject};
+use super::storage::Storage;
+use chrono::{TimeZone, Utc};
+use flate2::read::MultiGzDecoder;
+use futures::channel::mpsc;
+use futures::stream::BoxStream;
+use futures::{SinkExt, StreamExt};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::Field;
+use rusoto_s3::GetObjectRequest;
+use serde::Deserialize;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+
+/// Where the manifest.json of an S3 Inventory report is read from
+#[derive(Debug, Clone, PartialEq)]
+pub enum InventoryLocation {
+    Local(PathBuf),
+    S3 { bucket: String, key: String },
+}
+
+impl fmt::Display for InventoryLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InventoryLocation::Local(path) => write!(f, "{}", path.display()),
+            InventoryLocation::S3 { bucket, key } => write!(f, "s3://{}/{}", bucket, key),
+        }
+    }
+}
+
+/// The manifest.json written with each S3 Inventory report
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InventoryManifest {
+    source_bucket: String,
+    /// ARN of the bucket holding the data files, e.g. arn:aws:s3:::inventory-bucket
+    destination_bucket: String,
+    file_format: String,
+    /// Comma separated column names of CSV data files
+    #[serde(default)]
+    file_schema: String,
+    files: Vec<InventoryFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct InventoryFile {
+    key: String,
+}
+
+/// Format of the data files of a report
+#[derive(Debug, Clone)]
+enum InventoryFormat {
+    /// Gzipped CSV, with the columns given in the manifest
+    Csv {
+        schema: String,
+    },
+    Parquet,
+}
+
+/// Keys (with their size and storage class) read from an S3 Inventory report, used in place of
+/// listing the bucket
+///
+/// Only the current version of each key is included. The report may be up to a day old, so keys
+/// which have since been deleted fail to rename, and keys written since are not renamed.
+#[derive(Clone)]
+pub struct Inventory {
+    client: Arc<dyn Storage>,
+    source_bucket: String,
+    format: InventoryFormat,
+    /// Data files, whose keys are in lexicographic order (across files too)
+    files: Vec<InventoryLocation>,
+}
+
+impl Inventory {
+    /// Read the manifest of a report (CSV or Parquet), whose data files are read by `pages`
+    ///
+    /// Data files of a report in S3 are read with `client`. Data files of a local report are
+    /// looked up by their key under each parent directory of the manifest (i.e. a copy of the
+    /// inventory bucket), and otherwise next to the manifest.
+    pub async fn read(
+        client: Arc<dyn Storage>,
+        location: &InventoryLocation,
+    ) -> Result<Self, InventoryError> {
+        let manifest = read_file(&*client, location).await?;
+        let manifest: InventoryManifest =
+            serde_json::from_slice(&manifest).map_err(|error| InventoryError::InvalidManifest {
+                location: location.to_string(),
+                error,
+            })?;
+        let format = match manifest.file_format.to_uppercase().as_str() {
+            "CSV" => InventoryFormat::Csv {
+                schema: manifest.file_schema.clone(),
+            },
+            "PARQUET" => InventoryFormat::Parquet,
+            "ORC" => return Err(InventoryError::OrcUnsupported),
+            _ => {
+                return Err(InventoryError::UnsupportedFormat {
+                    format: manifest.file_format,
+                })
+            }
+        };
+        let files = manifest
+            .files
+            .iter()
+            .map(|file| match location {
+                InventoryLocation::Local(path) => InventoryLocation::Local(local_data_file(
+                    path.parent().unwrap_or_else(|| Path::new("")),
+                    &file.key,
+                )),
+                InventoryLocation::S3 { .. } => InventoryLocation::S3 {
+                    bucket: manifest
+                        .destination_bucket
+                        .trim_start_matches("arn:aws:s3:::")
+                        .to_string(),
+                    key: file.key.clone(),
+                },
+            })
+            .collect();
+        Ok(Inventory {
+            client,
+            source_bucket: manifest.source_bucket,
+            format,
+            files,
+        })
+    }
+
+    /// Bucket the report lists keys of
+    pub fn source_bucket(&self) -> &str {
+        &self.source_bucket
+    }
+
+    /// Number of data files in the report
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Pages of keys under `prefix` (after `start_after`, if given) in lexicographic order
+    ///
+    /// Data files are read one at a time as the pages are consumed, so only a page of keys is held
+    /// in memory. Every file is read in full, failing if its keys are not in order after those
+    /// of the previous files. CSV files are decompressed as they are read, Parquet files are read
+    /// a row group at a time (after downloading the file, if it is in S3).
+    pub fn pages(
+        &self,
+        prefix: Option<String>,
+        start_after: Option<String>,
+    ) -> BoxStream<'static, Result<Vec<SourceObject>, InventoryError>> {
+        let (mut sender, receiver) = mpsc::channel(1);
+        let inventory = self.clone();
+        tokio::spawn(async move {
+            let mut pager = Pager {
+                prefix: prefix.unwrap_or_default(),
+                start_after,
+                last_key: None,
+                page: Vec::with_capacity(PAGE_SIZE),
+                sender: sender.clone(),
+            };
+            for location in inventory.files {
+                let data = match open_file(&*inventory.client, &location).await {
+                    Ok(data) => data,
+                    Err(error) => {
+                        let _ = pager.sender.send(Err(error)).await;
+                        return;
+                    }
+                };
+                let format = inventory.format.clone();
+                let read = tokio::task::spawn_blocking(move || {
+                    let result = match &format {
+                        InventoryFormat::Csv { schema } => read_csv(data, schema, &mut pager),
+                        InventoryFormat::Parquet => read_parquet(data, &mut pager),
+                    };
+                    (result, pager)
+                })
+                .await;
+                let (result, returned) = match read {
+                    Ok(read) => read,
+                    // The reading thread panicked, taking the pager with it
+                    Err(error) => {
+                        let error = InventoryError::InvalidDataFile {
+                            location: location.to_string(),
+                            error: error.to_string(),
+                        };
+                        let _ = sender.send(Err(error)).await;
+                        return;
+                    }
+                };
+                pager = returned;
+                match result {
+                    Ok(Reading::Continue) => {}
+                    Ok(Reading::Done) => break,
+                    Err(error) => {
+                        let error = InventoryError::InvalidDataFile {
+                            location: location.to_string(),
+                            error,
+                        };
+                        let _ = pager.sender.send(Err(error)).await;
+                        return;
+                    }
+                }
+            }
+            let page = std::mem::take(&mut pager.page);
+            if !page.is_empty() {
+                let _ = pager.sender.send(Ok(page)).await;
+            }
+        });
+        receiver.boxed()
+    }
+}
+
+/// Keys per page, the same as listing, so that --stream works in the same way
+const PAGE_SIZE: usize = 1000;
+
+/// Whether to read further data files
+enum Reading {
+    Continue,
+    /// The pages are no longer wanted
+    Done,
+}
+
+/// Collects the keys read from data files into pages, sending each page once it is full
+struct Pager {
+    prefix: String,
+    start_after: Option<String>,
+    /// Last key read, to check that keys are in order
+    last_key: Option<String>,
+    page: Vec<SourceObject>,
+    sender: mpsc::Sender<Result<Vec<SourceObject>, InventoryError>>,
+}
+
+impl Pager {
+    /// Add a key read from a data file (on a blocking thread), if it is under the prefix
+    fn push(&mut self, object: SourceObject) -> Result<Reading, String> {
+        if let Some(last) = &self.last_key {
+            if object.key < *last {
+                return Err(format!(
+                    "keys are not in lexicographic order: {:?} after {:?}",
+                    object.key, last
+                ));
+            }
+        }
+        self.last_key = Some(object.key.clone());
+        // Every row is read, so that a key out of order (e.g. under the prefix in a later file)
+        // is an error rather than silently skipped
+        if self.start_after.as_ref().is_some_and(|x| object.key <= *x)
+            || !object.key.starts_with(&self.prefix)
+        {
+            return Ok(Reading::Continue);
+        }
+        self.page.push(object);
+        if self.page.len() < PAGE_SIZE {
+            return Ok(Reading::Continue);
+        }
+        let page = std::mem::replace(&mut self.page, Vec::with_capacity(PAGE_SIZE));
+        match futures::executor::block_on(self.sender.send(Ok(page))) {
+            Ok(()) => Ok(Reading::Continue),
+            Err(_) => Ok(Reading::Done),
+        }
+    }
+}
+
+/// Path of a data file of a local report, see `Inventory::read`
+fn local_data_file(manifest_dir: &Path, key: &str) -> PathBuf {
+    manifest_dir
+        .ancestors()
+        .map(|x| x.join(key))
+        .find(|x| x.exists())
+        .unwrap_or_else(|| manifest_dir.join(Path::new(key).file_name().unwrap_or_default()))
+}
+
+async fn read_file(
+    client: &dyn Storage,
+    location: &InventoryLocation,
+) -> Result<Vec<u8>, InventoryError> {
+    let read_error = |error: String| InventoryError::Read {
+        location: location.to_string(),
+        error,
+    };
+    match location {
+        InventoryLocation::Local(path) => {
+            std::fs::read(path).map_err(|error| read_error(error.to_string()))
+        }
+        InventoryLocation::S3 { bucket, key } => {
+            let response = client
+                .get_object(GetObjectRequest {
+                    bucket: bucket.clone(),
+                    key: key.clone(),
+                    ..Default::default()
+                })
+                .await
+                .map_err(|error| read_error(error.to_string()))?;
+            let mut data = Vec::new();
+            if let Some(body) = response.body {
+                body.into_async_read()
+                    .read_to_end(&mut data)
+                    .await
+                    .map_err(|error| read_error(error.to_string()))?;
+            }
+            Ok(data)
+        }
+    }
+}
+
+/// An opened data file, read on a blocking thread
+enum DataFile {
+    Local(File),
+    S3(Box<dyn Read + Send>),
+}
+
+async fn open_file(
+    client: &dyn Storage,
+    location: &InventoryLocation,
+) -> Result<DataFile, InventoryError> {
+    let read_error = |error: String| InventoryError::Read {
+        location: location.to_string(),
+        error,
+    };
+    match location {
+        InventoryLocation::Local(path) => File::open(path)
+            .map(DataFile::Local)
+            .map_err(|error| read_error(error.to_string())),
+        InventoryLocation::S3 { bucket, key } => {
+            let response = client
+                .get_object(GetObjectRequest {
+                    bucket: bucket.clone(),
+                    key: key.clone(),
+                    ..Default::default()
+                })
+                .await
+                .map_err(|error| read_error(error.to_string()))?;
+            Ok(DataFile::S3(match response.body {
+                Some(body) => Box::new(body.into_blocking_read()),
+                None => Box::new(std::io::empty()),
+            }))
+        }
+    }
+}
+
+/// Read a (usually gzipped) CSV data file, whose columns are given by `schema`
+fn read_csv(data: DataFile, schema: &str, pager: &mut Pager) -> Result<Reading, String> {
+    let columns: Vec<&str> = schema.split(',').map(str::trim).collect();
+    let column = |name: &str| columns.iter().position(|x| x.eq_ignore_ascii_case(name));
+    let key_column = column("Key").ok_or("schema has no Key column")?;
+    let (size_column, storage_class_column) = (column("Size"), column("StorageClass"));
+    let (is_latest_column, is_delete_marker_column) =
+        (column("IsLatest"), column("IsDeleteMarker"));
+    let e_tag_column = column("ETag");
+    let last_modified_column = column("LastModifiedDate");
+
+    let mut data = BufReader::new(match data {
+        DataFile::Local(file) => Box::new(file) as Box<dyn Read + Send>,
+        DataFile::S3(body) => body,
+    });
+    let gzipped = data
+        .fill_buf()
+        .map_err(|x| x.to_string())?
+        .starts_with(&[0x1f, 0x8b]);
+    let data: Box<dyn Read> = if gzipped {
+        Box::new(MultiGzDecoder::new(data))
+    } else {
+        Box::new(data)
+    };
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(data);
+    for record in reader.records() {
+        let record = record.map_err(|x| x.to_string())?;
+        let field =
+            |column: Option<usize>| column.and_then(|x| record.get(x)).filter(|x| !x.is_empty());
+        if field(is_latest_column) == Some("false")
+            || field(is_delete_marker_column) == Some("true")
+        {
+            continue;
+        }
+        let key = field(Some(key_column)).ok_or("row has no key")?;
+        let object = SourceObject {
+            key: decode_key(key)?,
+            storage_class: field(storage_class_column).map(String::from),
+            size: field(size_column).and_then(|x| x.parse().ok()),
+            e_tag: field(e_tag_column).map(|x| format!("\"{}\"", x)),
+            last_modified: field(last_modified_column).and_then(parse_last_modified),
+        };
+        if let Reading::Done = pager.push(object)? {
+            return Ok(Reading::Done);
+        }
+    }
+    Ok(Reading::Continue)
+}
+
+/// Keys in CSV reports are URL encoded (with spaces as "+")
+fn decode_key(key: &str) -> Result<String, String> {
+    percent_encoding::percent_decode_str(&key.replace('+', " "))
+        .decode_utf8()
+        .map(|x| x.into_owned())
+        .map_err(|x| format!("invalid key {:?}: {}", key, x))
+}
+
+/// Read a Parquet data file, whose columns are named in snake case (e.g. storage_class)
+///
+/// Parquet files are read from their footer, so a file in S3 is downloaded before it is read.
+fn read_parquet(data: DataFile, pager: &mut Pager) -> Result<Reading, String> {
+    let reader: Box<dyn FileReader> = match data {
+        DataFile::Local(file) => {
+            Box::new(SerializedFileReader::new(file).map_err(|x| x.to_string())?)
+        }
+        DataFile::S3(mut body) => {
+            let mut data = Vec::new();
+            body.read_to_end(&mut data).map_err(|x| x.to_string())?;
+            let data = bytes1::Bytes::from(data);
+            Box::new(SerializedFileReader::new(data).map_err(|x| x.to_string())?)
+        }
+    };
+    for row in reader.get_row_iter(None).map_err(|x| x.to_string())? {
+        let row = row.map_err(|x| x.to_string())?;
+        let mut key = None;
+        let mut object = SourceObject {
+            key: String::new(),
+            storage_class: None,
+            size: None,
+            e_tag: None,
+            last_modified: None,
+        };
+        let mut current = true;
+        for (name, field) in row.get_column_iter() {
+            match (name.as_str(), field) {
+                ("key", Field::Str(x)) => key = Some(x.clone()),
+                ("storage_class", Field::Str(x)) => object.storage_class = Some(x.clone()),
+                ("size", Field::Long(x)) => object.size = Some(*x),
+                ("e_tag", Field::Str(x)) => object.e_tag = Some(format!("\"{}\"", x)),
+                ("last_modified_date", Field::TimestampMillis(x)) => {
+                    object.last_modified = Utc.timestamp_millis_opt(*x).single()
+                }
+                ("is_latest", Field::Bool(false)) | ("is_delete_marker", Field::Bool(true)) => {
+                    current = false
+                }
+                _ => {}
+            }
+        }
+        if current {
+            object.key = key.ok_or("row has no key")?;
+            if let Reading::Done = pager.push(object)? {
+                return Ok(Reading::Done);
+            }
+        }
+    }
+    Ok(Reading::Continue)
+}
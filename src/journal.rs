@@ -0,0 +1,215 @@
+use super::errors::JournalError;
+use super::events::RenameStage;
+use super::jsonl::{read_json_lines, JsonLinesWriter, ReadLinesError};
+use super::listing::SourceObject;
+use super::renamer::RenameOptions;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// A single line of the journal
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "entry", rename_all = "snake_case")]
+pub enum JournalEntry {
+    /// The options the run was started with (always the first entry)
+    Started {
+        bucket: String,
+        prefix: Option<String>,
+        expr: String,
+    },
+    /// A key found by listing, in listing (i.e. lexicographic) order
+    Listed {
+        source: String,
+        storage_class: Option<String>,
+        size: Option<i64>,
+    },
+    /// All keys under the prefix have been listed
+    ListingComplete,
+    Copied {
+        source: String,
+        target: String,
+    },
+    Verified {
+        source: String,
+        target: String,
+    },
+    Deleted {
+        source: String,
+        target: String,
+    },
+}
+
+/// Append-only record of the progress of a run, used to resume it with `--resume`
+///
+/// Each entry is written as a line of JSON and flushed immediately, so the journal is up to date
+/// if the process crashes.
+#[derive(Debug)]
+pub struct Journal {
+    writer: JsonLinesWriter,
+}
+
+impl Journal {
+    /// Create a new journal for a run with the given options, overwriting any existing file
+    pub fn create(path: &Path, options: &RenameOptions) -> Result<Self, JournalError> {
+        let writer = JsonLinesWriter::create(path).map_err(|error| JournalError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        let journal = Journal { writer };
+        journal.record(&JournalEntry::Started {
+            bucket: options.bucket.clone(),
+            prefix: options.key_prefix.clone(),
+            expr: options.expr.clone(),
+        })?;
+        Ok(journal)
+    }
+
+    /// Load an existing journal, returning it (opened for appending) and the state of the run
+    ///
+    /// Fails if the journal was written by a run with a different bucket, prefix or expression.
+    pub fn resume(
+        path: &Path,
+        options: &RenameOptions,
+    ) -> Result<(Self, ResumeState), JournalError> {
+        let state = ResumeState::load(path, options)?;
+        let writer = JsonLinesWriter::append(path).map_err(|error| JournalError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        Ok((Journal { writer }, state))
+    }
+
+    pub fn path(&self) -> &Path {
+        self.writer.path()
+    }
+
+    pub fn record(&self, entry: &JournalEntry) -> Result<(), JournalError> {
+        self.writer.write(entry).map_err(|error| JournalError::Io {
+            path: self.writer.path().to_path_buf(),
+            error,
+        })
+    }
+}
+
+/// The progress of a previous run, loaded from its journal
+#[derive(Debug, Default)]
+pub struct ResumeState {
+    /// Listed keys which were not deleted, in listing order
+    remaining: Vec<SourceObject>,
+    /// Last key listed, listing continues after this if it was not complete
+    last_listed: Option<String>,
+    listing_complete: bool,
+    /// Target key and last stage of keys which were copied but not deleted
+    incomplete: HashMap<String, (String, RenameStage)>,
+    /// Target keys of all keys which were copied
+    targets: HashSet<String>,
+}
+
+impl ResumeState {
+    fn load(path: &Path, options: &RenameOptions) -> Result<Self, JournalError> {
+        let entries: Vec<JournalEntry> = read_json_lines(path).map_err(|error| match error {
+            ReadLinesError::Io(error) => JournalError::Io {
+                path: path.to_path_buf(),
+                error,
+            },
+            ReadLinesError::Parse { line, error } => JournalError::Parse {
+                path: path.to_path_buf(),
+                line,
+                error,
+            },
+        })?;
+
+        let mut listed = Vec::new();
+        let mut deleted = HashSet::new();
+        let mut state = ResumeState::default();
+        let mut started = false;
+        for entry in entries {
+            match entry {
+                JournalEntry::Started {
+                    bucket,
+                    prefix,
+                    expr,
+                } => {
+                    if bucket != options.bucket
+                        || prefix != options.key_prefix
+                        || expr != options.expr
+                    {
+                        return Err(JournalError::OptionsMismatch {
+                            path: path.to_path_buf(),
+                        });
+                    }
+                    started = true;
+                }
+                JournalEntry::Listed {
+                    source,
+                    storage_class,
+                    size,
+                } => {
+                    state.last_listed = Some(source.clone());
+                    listed.push(SourceObject {
+                        key: source,
+                        storage_class,
+                        size,
+                        e_tag: None,
+                        // Checked against any filters with a HeadObject request if needed
+                        last_modified: None,
+                    });
+                }
+                JournalEntry::ListingComplete => state.listing_complete = true,
+                JournalEntry::Copied { source, target } => {
+                    state.targets.insert(target.clone());
+                    state
+                        .incomplete
+                        .insert(source, (target, RenameStage::Copied));
+                }
+                JournalEntry::Verified { source, target } => {
+                    state.targets.insert(target.clone());
+                    state
+                        .incomplete
+                        .insert(source, (target, RenameStage::Verified));
+                }
+                JournalEntry::Deleted { source, target } => {
+                    state.targets.insert(target);
+                    state.incomplete.remove(&source);
+                    deleted.insert(source);
+                }
+            }
+        }
+        if !started {
+            return Err(JournalError::OptionsMismatch {
+                path: path.to_path_buf(),
+            });
+        }
+
+        state.remaining = listed
+            .into_iter()
+            .filter(|x| !deleted.contains(&x.key))
+            .collect();
+        Ok(state)
+    }
+
+    /// Listed keys which have not been deleted, in listing order
+    pub fn remaining(&self) -> &[SourceObject] {
+        &self.remaining
+    }
+
+    /// Whether all keys under the prefix were listed
+    pub fn listing_complete(&self) -> bool {
+        self.listing_complete
+    }
+
+    /// The last key listed, listing continues after this if it was not complete
+    pub fn last_listed(&self) -> Option<&str> {
+        self.last_listed.as_deref()
+    }
+
+    /// Target and stage of a key which was copied but not deleted
+    pub fn incomplete(&self, source: &str) -> Option<&(String, RenameStage)> {
+        self.incomplete.get(source)
+    }
+
+    /// Whether the key was created by the previous run
+    pub fn is_target(&self, key: &str) -> bool {
+        self.targets.contains(key)
+    }
+}
@@ -0,0 +1,95 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A file written one line of JSON at a time, shared between concurrent tasks
+///
+/// Each line is flushed as soon as it is written, so the file is up to date if the process
+/// crashes.
+#[derive(Debug)]
+pub(crate) struct JsonLinesWriter {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl JsonLinesWriter {
+    /// Create the file, overwriting any existing file
+    pub(crate) fn create(path: &Path) -> std::io::Result<Self> {
+        Ok(JsonLinesWriter {
+            path: path.to_path_buf(),
+            file: Mutex::new(File::create(path)?),
+        })
+    }
+
+    /// Open an existing file to append lines to it
+    pub(crate) fn append(path: &Path) -> std::io::Result<Self> {
+        Ok(JsonLinesWriter {
+            path: path.to_path_buf(),
+            file: Mutex::new(OpenOptions::new().append(true).open(path)?),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn write<T: Serialize>(&self, value: &T) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(value)?;
+        line.push('\n');
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())?;
+        file.flush()
+    }
+}
+
+/// Error reading a file written by `JsonLinesWriter`
+#[derive(Debug)]
+pub(crate) enum ReadLinesError {
+    Io(std::io::Error),
+    Parse {
+        /// Line number, starting from 1
+        line: usize,
+        error: serde_json::Error,
+    },
+}
+
+/// Read every line of a file written by `JsonLinesWriter`
+///
+/// An unparseable last line is ignored, since it may be incomplete if the process was killed
+/// while writing it.
+pub(crate) fn read_json_lines<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>, ReadLinesError> {
+    read_lines(path, true)
+}
+
+/// Read every line of a file written by hand or by another tool (e.g. a mapping file), in which
+/// every line must parse
+pub(crate) fn read_json_lines_strict<T: DeserializeOwned>(
+    path: &Path,
+) -> Result<Vec<T>, ReadLinesError> {
+    read_lines(path, false)
+}
+
+/// Read each non-blank line as JSON, ignoring an unparseable last line if `allow_truncated`
+fn read_lines<T: DeserializeOwned>(
+    path: &Path,
+    allow_truncated: bool,
+) -> Result<Vec<T>, ReadLinesError> {
+    let file = File::open(path).map_err(ReadLinesError::Io)?;
+    let mut lines = BufReader::new(file).lines().enumerate().peekable();
+    let mut values = Vec::new();
+    while let Some((i, line)) = lines.next() {
+        let line = line.map_err(ReadLinesError::Io)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(value) => values.push(value),
+            Err(_) if allow_truncated && lines.peek().is_none() => break,
+            Err(error) => return Err(ReadLinesError::Parse { line: i + 1, error }),
+        }
+    }
+    Ok(values)
+}
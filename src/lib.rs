@@ -0,0 +1,46 @@
+//! s3rename renames keys within an S3 bucket using Perl-style regular expressions.
+//!
+//! The command line tool is a thin wrapper around `Renamer`, which can also be used to carry out
+//! renames in-process.
+#[macro_use]
+extern crate lazy_static;
+pub mod args;
+mod client;
+mod copy;
+pub mod errors;
+mod events;
+mod expression;
+mod filter;
+mod inventory;
+mod journal;
+mod jsonl;
+mod listing;
+mod manifest;
+mod mapping;
+pub mod memory_storage;
+mod multipart_copy;
+mod ordering;
+mod plan;
+mod progress;
+mod renamer;
+mod report;
+mod retry;
+pub mod storage;
+mod summary;
+mod tracker;
+
+pub use client::{client_for_bucket, ConnectionOptions};
+pub use events::{RenameEvent, RenameStage, SkipReason};
+pub use filter::{FilterAction, KeyFilter, KeyFilters, KeyPattern, ObjectFilters};
+pub use inventory::{Inventory, InventoryLocation};
+pub use journal::{Journal, JournalEntry, ResumeState};
+pub use manifest::{Manifest, ManifestEntry};
+pub use mapping::read_mapping;
+pub use multipart_copy::MultipartOptions;
+pub use plan::{Plan, PlanOptions};
+pub use progress::Progress;
+pub use renamer::{PlannedRename, RenameOptions, Renamer, ShutdownHandle};
+pub use report::{Report, ReportAction, ReportFormat, ReportRecord};
+pub use retry::{RetryCounter, RetryPolicy};
+pub use summary::RunSummary;
+pub use tracker::{IncompleteKey, StageTracker};
@@ -0,0 +1,357 @@
+use super::errors::S3Error;
+use super::storage::Storage;
+use chrono::{DateTime, Utc};
+use futures::future::{self, BoxFuture, FutureExt};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use rusoto_s3::{ListObjectsV2Request, Object};
+
+/// A key to be renamed, with the properties known from listing
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceObject {
+    pub key: String,
+    pub storage_class: Option<String>,
+    /// Object size in bytes
+    pub size: Option<i64>,
+    /// ETag when listed (or when planned, for renames of exact keys)
+    pub e_tag: Option<String>,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// Parse the last modified time of an object, as given by ListObjectsV2 (RFC 3339) or HeadObject
+/// (an HTTP date, i.e. RFC 2822)
+pub(crate) fn parse_last_modified(time: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time)
+        .or_else(|_| DateTime::parse_from_rfc2822(time))
+        .map(|x| x.with_timezone(&Utc))
+        .ok()
+}
+
+/// Keys under a prefix, listed one page (i.e. one ListObjectsV2 request) at a time
+pub struct KeyPages<'a> {
+    client: &'a dyn Storage,
+    bucket: String,
+    prefix: Option<String>,
+    start_after: Option<String>,
+    continuation_token: Option<String>,
+    done: bool,
+    /// Whether finding no keys is expected rather than an error
+    allow_empty: bool,
+}
+
+impl<'a> KeyPages<'a> {
+    /// List keys under `prefix`, starting after `start_after` if given
+    pub fn new(
+        client: &'a dyn Storage,
+        bucket: &str,
+        prefix: Option<String>,
+        start_after: Option<String>,
+    ) -> Self {
+        KeyPages {
+            client,
+            bucket: String::from(bucket),
+            prefix,
+            // Continuing a listing may legitimately find no further keys
+            allow_empty: start_after.is_some(),
+            start_after,
+            continuation_token: None,
+            done: false,
+        }
+    }
+
+    /// Fetch the next page of keys (with their storage class and size), or `None` once all keys
+    /// have been listed
+    pub async fn next_page(&mut self) -> Result<Option<Vec<SourceObject>>, anyhow::Error> {
+        if self.done {
+            return Ok(None);
+        }
+        let response = self
+            .client
+            .list_objects_v2(list_request(
+                &self.bucket,
+                self.prefix.clone(),
+                None,
+                self.continuation_token.take(),
+                self.start_after.clone(),
+            ))
+            .await?;
+
+        // Set new continuation_token from response
+        self.continuation_token = response.next_continuation_token.clone();
+        // Stop if keys were not truncated (i.e. no more keys)
+        self.done = response.is_truncated != Some(true);
+
+        let objects_inner = match response.contents {
+            None if self.allow_empty => Vec::new(),
+            // Note we return an error on no matching keys, may want to succeed silently
+            None => {
+                return Err(S3Error::EmptyBucket {
+                    bucket: self.bucket.clone(),
+                    prefix: self.prefix.clone().unwrap_or_default(),
+                }
+                .into())
+            }
+            Some(x) => x,
+        };
+        Ok(Some(source_objects(objects_inner).collect()))
+    }
+}
+
+/// A key or common prefix found by listing with a delimiter
+enum Entry {
+    Key(SourceObject),
+    Prefix(String),
+}
+
+impl Entry {
+    fn name(&self) -> &str {
+        match self {
+            Entry::Key(x) => &x.key,
+            Entry::Prefix(x) => x,
+        }
+    }
+}
+
+/// List keys under `prefix` a page at a time, in lexicographic order
+///
+/// If `depth` is greater than 0, the prefix is first listed with a "/" delimiter, recursively
+/// for `depth` levels of common prefixes, and then the keys under each common prefix found are
+/// listed with up to `parallelism` prefixes listed at once. The first page of each prefix is
+/// fetched ahead, and the rest of its pages as they are consumed, so at most `parallelism` pages
+/// are held in memory.
+pub fn list_pages<'a>(
+    client: &'a dyn Storage,
+    bucket: &'a str,
+    prefix: Option<String>,
+    start_after: Option<String>,
+    depth: usize,
+    parallelism: usize,
+) -> BoxStream<'a, Result<Vec<SourceObject>, anyhow::Error>> {
+    if depth == 0 {
+        return remaining_pages(KeyPages::new(client, bucket, prefix, start_after));
+    }
+    let parallelism = parallelism.max(1);
+    stream::once(discover_prefixes(
+        client,
+        bucket,
+        prefix,
+        start_after.clone(),
+        depth,
+        parallelism,
+    ))
+    .map_ok(move |entries| {
+        // Keys found while discovering prefixes are returned between the prefixes either side of
+        // them, so that every key is returned in order
+        let mut parts: Vec<BoxFuture<'a, Result<PageStream<'a>, anyhow::Error>>> = Vec::new();
+        let mut keys = Vec::new();
+        for entry in entries {
+            match entry {
+                Entry::Key(key) => keys.push(key),
+                Entry::Prefix(prefix) => {
+                    if !keys.is_empty() {
+                        let page = stream::once(future::ok(std::mem::take(&mut keys)));
+                        parts.push(future::ok(page.boxed()).boxed());
+                    }
+                    let mut pages =
+                        KeyPages::new(client, bucket, Some(prefix), start_after.clone());
+                    // The prefix may have been emptied since it was listed
+                    pages.allow_empty = true;
+                    parts.push(
+                        async move {
+                            let first = pages.next_page().await?;
+                            Ok(stream::iter(first.map(Ok))
+                                .chain(remaining_pages(pages))
+                                .boxed())
+                        }
+                        .boxed(),
+                    );
+                }
+            }
+        }
+        if !keys.is_empty() {
+            parts.push(future::ok(stream::once(future::ok(keys)).boxed()).boxed());
+        }
+        stream::iter(parts).buffered(parallelism).try_flatten()
+    })
+    .try_flatten()
+    .boxed()
+}
+
+type PageStream<'a> = BoxStream<'a, Result<Vec<SourceObject>, anyhow::Error>>;
+
+/// Fetch the pages of `pages` not yet fetched, one at a time as they are consumed
+fn remaining_pages(pages: KeyPages<'_>) -> PageStream<'_> {
+    stream::try_unfold(pages, |mut pages| async move {
+        Ok(pages.next_page().await?.map(|page| (page, pages)))
+    })
+    .boxed()
+}
+
+/// List `prefix` with a "/" delimiter for `depth` levels, returning the keys found along the way
+/// and the common prefixes at the last level, sorted by name
+async fn discover_prefixes(
+    client: &dyn Storage,
+    bucket: &str,
+    prefix: Option<String>,
+    start_after: Option<String>,
+    depth: usize,
+    parallelism: usize,
+) -> Result<Vec<Entry>, anyhow::Error> {
+    let mut entries = Vec::new();
+    let mut level = vec![prefix.clone().unwrap_or_default()];
+    for _ in 0..depth {
+        let listed: Vec<Vec<Entry>> = stream::iter(
+            level
+                .into_iter()
+                .map(|prefix| list_delimited(client, bucket, prefix, start_after.clone())),
+        )
+        .buffer_unordered(parallelism)
+        .try_collect()
+        .await?;
+        level = Vec::new();
+        for entry in listed.into_iter().flatten() {
+            match entry {
+                Entry::Prefix(prefix) => level.push(prefix),
+                key => entries.push(key),
+            }
+        }
+        if level.is_empty() {
+            break;
+        }
+    }
+    entries.extend(level.into_iter().map(Entry::Prefix));
+
+    // Note we return an error on no matching keys, may want to succeed silently
+    if entries.is_empty() && start_after.is_none() {
+        return Err(S3Error::EmptyBucket {
+            bucket: String::from(bucket),
+            prefix: prefix.unwrap_or_default(),
+        }
+        .into());
+    }
+    entries.sort_by(|a, b| a.name().cmp(b.name()));
+    Ok(entries)
+}
+
+/// List the keys and common prefixes directly under `prefix`
+async fn list_delimited(
+    client: &dyn Storage,
+    bucket: &str,
+    prefix: String,
+    start_after: Option<String>,
+) -> Result<Vec<Entry>, anyhow::Error> {
+    let mut entries = Vec::new();
+    let mut continuation_token = None;
+    loop {
+        let response = client
+            .list_objects_v2(list_request(
+                bucket,
+                Some(prefix.clone()).filter(|x| !x.is_empty()),
+                Some(String::from("/")),
+                continuation_token,
+                start_after.clone(),
+            ))
+            .await?;
+        continuation_token = response.next_continuation_token;
+        entries.extend(
+            source_objects(response.contents.unwrap_or_default())
+                .map(Entry::Key)
+                .chain(
+                    response
+                        .common_prefixes
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(|x| x.prefix)
+                        .map(Entry::Prefix),
+                ),
+        );
+        if response.is_truncated != Some(true) {
+            return Ok(entries);
+        }
+    }
+}
+
+fn list_request(
+    bucket: &str,
+    prefix: Option<String>,
+    delimiter: Option<String>,
+    continuation_token: Option<String>,
+    start_after: Option<String>,
+) -> ListObjectsV2Request {
+    ListObjectsV2Request {
+        bucket: String::from(bucket),
+        continuation_token,
+        delimiter,
+        encoding_type: None,
+        fetch_owner: None,
+        max_keys: None,
+        prefix,
+        request_payer: None,
+        start_after,
+    }
+}
+
+/// Get keys out of a ListObjectsV2 response
+fn source_objects(objects: Vec<Object>) -> impl Iterator<Item = SourceObject> {
+    objects
+        .into_iter()
+        .filter(|x| x.key.is_some())
+        .map(|x| SourceObject {
+            key: x.key.unwrap(),
+            storage_class: x.storage_class,
+            size: x.size,
+            e_tag: x.e_tag,
+            last_modified: x.last_modified.as_deref().and_then(parse_last_modified),
+        })
+}
+
+/// Whether `key` is a "directory" marker, i.e. an empty object whose key ends in "/" (as created
+/// by the S3 console for folders)
+pub(crate) fn is_directory_marker(key: &str) -> bool {
+    key.ends_with('/')
+}
+
+/// The "directories" containing `key`, i.e. each prefix of it ending in "/" (excluding the key
+/// itself), from the top down
+pub(crate) fn parent_directories(key: &str) -> impl Iterator<Item = &str> {
+    let parent = key.strip_suffix('/').unwrap_or(key);
+    parent.match_indices('/').map(move |(i, _)| &key[..=i])
+}
+
+/// Whether the directory marker `marker` exists and no other keys are under it
+pub(crate) async fn is_empty_marker(
+    client: &dyn Storage,
+    bucket: &str,
+    marker: &str,
+) -> Result<bool, anyhow::Error> {
+    let response = client
+        .list_objects_v2(ListObjectsV2Request {
+            max_keys: Some(2),
+            ..list_request(bucket, Some(String::from(marker)), None, None, None)
+        })
+        .await?;
+    let keys: Vec<Option<String>> = response
+        .contents
+        .unwrap_or_default()
+        .into_iter()
+        .map(|x| x.key)
+        .collect();
+    Ok(keys == [Some(String::from(marker))])
+}
+
+/// List all keys (with their storage class and size) under `prefix`, starting after
+/// `start_after` if given
+///
+/// Every key is held in memory, use `list_pages` to process keys a page at a time.
+pub async fn list_keys(
+    client: &dyn Storage,
+    bucket: &str,
+    prefix: Option<String>,
+    start_after: Option<String>,
+) -> Result<Vec<SourceObject>, anyhow::Error> {
+    let mut pages = KeyPages::new(client, bucket, prefix, start_after);
+    let mut keys_vec = Vec::new();
+    while let Some(page) = pages.next_page().await? {
+        keys_vec.extend(page);
+    }
+    Ok(keys_vec)
+}
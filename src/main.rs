@@ -1,447 +1,231 @@
-#[macro_use]
-extern crate lazy_static;
-mod args;
-mod errors;
-mod wrapped_copy;
-
-use std::sync::Arc;
-use std::sync::Mutex;
-
-use anyhow::Result;
-use args::CannedACL;
-use core::str::FromStr;
-use errors::{ArgumentError, GranteeParseError};
-use errors::{ExpressionError, S3Error};
 use futures::stream::StreamExt;
-use log::{debug, info};
-use rusoto_core::Region;
-use rusoto_s3::{CopyObjectRequest, GetObjectAclRequest, HeadObjectRequest};
-use rusoto_s3::{GetBucketLocationRequest, ListObjectsV2Request};
-use rusoto_s3::{Grantee, S3Client, S3};
-use sedregex::ReplaceCommand;
-use structopt::StructOpt;
-use wrapped_copy::WrappedCopyRequest;
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::kv::{self, Key, Value, VisitSource};
+use log::{debug, error, info, warn};
+use s3rename::args::{self, LogFormat};
+use s3rename::{
+    Progress, RenameEvent, RenameStage, Renamer, RunSummary, ShutdownHandle, StageTracker,
+};
+use std::fmt;
+use std::io::IsTerminal;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Exit code when some keys failed to rename but others were renamed
+const EXIT_PARTIAL_FAILURE: i32 = 2;
+/// Exit code when every key which was attempted failed to rename
+const EXIT_TOTAL_FAILURE: i32 = 3;
+/// Exit code when the run was interrupted by Ctrl-C / SIGTERM
+const EXIT_INTERRUPTED: i32 = 130;
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    let opt = args::App::from_args();
-    if let Err(e) = setup_logger(opt.verbose, opt.quiet) {
+    let opt = args::App::parse();
+    // Only drawn once renaming starts, see below
+    let progress_bar = ProgressBar::hidden();
+    if let Err(e) = setup_logger(&opt, progress_bar.clone()) {
         eprintln!("Could not set up logger: {}", e);
         std::process::exit(1);
     }
 
     debug!("{:?}", &opt);
-    let client = S3Client::new(opt.aws_region.clone().unwrap_or_default());
-
-    let bucket_region: Option<Region> = match client
-        .get_bucket_location(GetBucketLocationRequest {
-            bucket: opt.s3_url.bucket.clone(),
-        })
-        .await?
-        .location_constraint
-        .map(|x| Region::from_str(&x))
-    {
-        None => None,
-        Some(Err(_err)) => None, // Note we ignore failure to get bucket region
-        Some(Ok(aws_region)) => Some(aws_region),
-    };
-
-    let target_region = match (opt.aws_region.clone(), bucket_region) {
-        (Some(aws_region), _) => Ok(aws_region),
-        (None, Some(bucket_region)) => Ok(bucket_region),
-        (None, None) => Err(ArgumentError::CouldNotDetermineBucketRegion {
-            bucket: opt.s3_url.bucket.clone(), // TODO: try fallback to AWS config here?
-        }),
-    }?;
-
-    debug!("{:?}", target_region);
-    let client = Arc::new(S3Client::new(target_region));
-
-    // Collect all keys under prefix to this Vec (can we avoid this allocation)?
-    let mut keys_vec = Vec::new(); // Can we use metadata request to estimate size here?
-    let mut continuation_token = None;
-
-    loop {
-        // Here we loop until we are told that the request was not truncated (i.e. we have seen all
-        // keys)
-        let response = client
-            .list_objects_v2(ListObjectsV2Request {
-                bucket: opt.s3_url.bucket.clone(),
-                continuation_token,
-                delimiter: None,
-                encoding_type: None,
-                fetch_owner: None,
-                max_keys: None,
-                prefix: opt.s3_url.key_prefix.clone(),
-                request_payer: None,
-                start_after: None,
-            })
-            .await?;
-
-        // Set new continuation_token from response
-        continuation_token = response.continuation_token.clone();
-
-        let objects_inner = match response.contents {
-            // Note we return an error on no matching keys, may want to succeed silently
-            None => Err(S3Error::EmptyBucket {
-                bucket: opt.s3_url.bucket.clone(),
-                prefix: if let Some(prefix) = opt.s3_url.key_prefix.clone() {
-                    prefix
-                } else {
-                    String::new()
-                },
-            }),
-            Some(x) => Ok(x),
-        }?;
-
-        // Get keys out of response
-        let objects_inner = objects_inner
-            .into_iter()
-            .filter(|x| x.key.is_some())
-            .map(|x| (x.key.unwrap(), x.storage_class))
-            .filter(|x| !x.0.ends_with('/')); // Skip "directory" keys - TODO: check issues regarding empty directories
-
-        keys_vec.extend(objects_inner);
+    let renamer = Renamer::from_app(&opt).await?;
+    if let Some(args::Command::Plan { output, .. }) = &opt.command {
+        let plan = renamer.plan().await?;
+        plan.write(output)?;
+        info!(
+            "Wrote plan of {} renames to {}",
+            plan.renames.len(),
+            output.display()
+        );
+        return Ok(());
+    }
+    let shutdown = renamer.shutdown_handle();
+    let retries = renamer.retry_counter();
+    let journal = renamer.journal_path();
+    let tracker = Arc::new(Mutex::new(StageTracker::default()));
+    tokio::spawn(handle_signals(shutdown.clone(), tracker.clone()));
+    // Logging is left clean when output is redirected (e.g. in CI)
+    if !opt.no_progress && std::io::stdout().is_terminal() {
+        progress_bar.set_draw_target(ProgressDrawTarget::stderr());
+        tokio::spawn(update_progress_bar(
+            progress_bar.clone(),
+            renamer.progress(),
+        ));
+    }
 
-        // Break loop if keys were not truncated (i.e. no more keys)
-        match response.is_truncated {
-            Some(true) => {}
-            _ => {
+    let mut summary = RunSummary::default();
+    // An error which stopped the run, reported after the summary of the keys handled before it
+    let mut fatal = None;
+    let mut events = renamer.run();
+    while let Some(event) = events.next().await {
+        let event = match event {
+            Ok(event) => event,
+            Err(error) => {
+                fatal = Some(error);
                 break;
             }
+        };
+        tracker.lock().unwrap().update(&event);
+        summary.update(&event);
+        progress_bar.set_position(summary.processed() as u64);
+        match event {
+            RenameEvent::Planned { source, target } => {
+                info!(
+                    key = source.as_str(), new_key = target.as_str(), action = "renaming";
+                    "Renaming {} to {}", source, target
+                );
+            }
+            RenameEvent::Skipped {
+                source,
+                target,
+                reason,
+            } => {
+                debug!(
+                    key = source.as_str(), new_key = target.as_str(), action = "skipped",
+                    reason:% = reason;
+                    "Skipping {}: {}", source, reason
+                );
+            }
+            RenameEvent::Copied { .. } | RenameEvent::Verified { .. } => {}
+            RenameEvent::Deleted { source, target } => {
+                debug!(
+                    key = source.as_str(), new_key = target.as_str(), action = "renamed";
+                    "Deleted {}", source
+                );
+            }
+            RenameEvent::Failed {
+                source,
+                target: Some(target),
+                stage: stage @ RenameStage::Copied,
+                error,
+                request_id,
+            }
+            | RenameEvent::Failed {
+                source,
+                target: Some(target),
+                stage: stage @ RenameStage::Verified,
+                error,
+                request_id,
+            } => {
+                error!(
+                    key = source.as_str(), new_key = target.as_str(), action = "failed",
+                    stage:% = stage, error = error.as_str(), request_id = request_id.as_deref();
+                    "Failed to rename {} (key was {} and now exists at both {} and {}): {}",
+                    source, stage, source, target, error
+                );
+            }
+            RenameEvent::Failed {
+                source,
+                target,
+                stage,
+                error,
+                request_id,
+            } => {
+                error!(
+                    key = source.as_str(), new_key = target.as_deref(), action = "failed",
+                    stage:% = stage, error = error.as_str(), request_id = request_id.as_deref();
+                    "Failed to rename {}: {}", source, error
+                );
+            }
         }
     }
-
-    debug!("{:?}", &keys_vec);
-
-    // Used to store futures returned from destructors (so we do not terminate until destructors
-    // have finished) - this pseudo-async destructor setup might violate atomicity (since a
-    // terminate request will guarantee destructors run but not that the spawned async DeleteObject
-    // requests finish). The whole issue here is that we cannot .await() inside the .drop()
-    // method as it is not async.
-    let destructor_futures = Arc::new(Mutex::new(futures::stream::FuturesUnordered::new()));
-    let mut futures = futures::stream::FuturesUnordered::new();
-
-    let parsed_string = if !opt.no_anonymous_groups {
-        // Pre-parse regex to allow \N syntax for capture groups (as well as $N)
-        // This is a heuristic, we do not check if preceding backslash was already escaped
-        // Can be disabled with --no-anonymous-groups flag
-        let capture_regex = regex::Regex::new("\\\\(?P<index>[0-9])")?;
-        let original_string = opt.expr.clone();
-        let parsed_string = capture_regex.replace_all(&original_string, "$$$index");
-
-        debug!("{}", parsed_string);
-        parsed_string.into_owned()
+    progress_bar.finish_and_clear();
+    let summary_line = format!("{} ({} requests retried)", summary, retries.get());
+    if summary.failed > 0 || fatal.is_some() {
+        warn!("{}", summary_line);
     } else {
-        opt.expr.clone()
-    };
-
-    // We leak Box to get &'static str which is thread-safe and can be put inside ReplaceCommand
-    let static_str: &'static str = Box::leak(parsed_string.into_boxed_str());
-
-    let replace_command = match ReplaceCommand::new(static_str) {
-        Ok(x) => Ok(x),
-        Err(err) => Err(ExpressionError::SedRegexParseError {
-            expression: opt.expr.clone(),
-            error: err,
-        }),
-    }?;
-
-    let replace_command = Arc::new(replace_command);
-
-    let bucket: Arc<str> = Arc::from(opt.s3_url.bucket.as_str());
-    let canned_acl = Arc::new(opt.canned_acl);
-    for key in keys_vec {
-        // TODO: Refactor this
-        let newclient = client.clone();
-        let newbucket = bucket.clone();
-        let newreplace_command = replace_command.clone();
-        let new_destructor_futures = destructor_futures.clone();
-
-        let dry_run = opt.dry_run;
-        let no_preserve_properties = opt.no_preserve_properties;
-        let no_preserve_acl = opt.no_preserve_acl;
-        let no_overwrite = opt.no_overwrite;
-        let new_canned_acl = canned_acl.clone();
-        futures.push(tokio::spawn(handle_key(
-            newclient,
-            newbucket,
-            key,
-            newreplace_command,
-            dry_run,
-            no_preserve_properties,
-            no_preserve_acl,
-            no_overwrite,
-            new_canned_acl,
-            new_destructor_futures.clone(),
-        )));
+        info!("{}", summary_line);
     }
-    while let Some(_handled) = futures.next().await {}
-
-    // Does Mutex make sense?
-    while let Some(_handled) = destructor_futures.lock().unwrap().next().await {}
-
-    Ok(())
-}
-
-#[allow(clippy::too_many_arguments)]
-async fn handle_key(
-    client: Arc<S3Client>,
-    bucket: Arc<str>,
-    key: (String, Option<String>),
-    replace_command: Arc<ReplaceCommand<'_>>,
-    dry_run: bool,
-    no_preserve_properties: bool,
-    no_preserve_acl: bool,
-    no_overwrite: bool,
-    canned_acl: Arc<Option<CannedACL>>,
-    destructor_futures: Arc<Mutex<futures::stream::FuturesUnordered<tokio::task::JoinHandle<()>>>>,
-) -> Result<(), anyhow::Error> {
-    let newkey = replace_command.execute(&key.0);
-    if newkey == key.0 {
-        debug!("Skipping {:?} since key did not change", key);
-        return Ok(());
+    if let Some(error) = fatal {
+        return Err(error);
     }
-    if no_overwrite {
-        let head_request = HeadObjectRequest {
-            bucket: (*bucket).to_string(),
-            if_match: None,
-            if_modified_since: None,
-            if_none_match: None,
-            if_unmodified_since: None,
-            key: String::from(newkey.to_owned()),
-            part_number: None,
-            range: None,
-            request_payer: None,
-            sse_customer_algorithm: None, // Cannot be empty if using sse-c
-            sse_customer_key: None,
-            sse_customer_key_md5: None,
-            version_id: None,
-        };
-        let head_result = client.head_object(head_request).await;
-        if let Ok(head_result) = head_result {
-            if head_result.metadata.is_some() {
-                debug!("Skipping {} since this would result in overwriting", newkey);
-                return Ok(());
-            }
+
+    if shutdown.is_shutdown() {
+        report_incomplete_keys(&tracker.lock().unwrap());
+        if let Some(journal) = &journal {
+            warn!("Continue the run with --resume {}", journal.display());
         }
+        std::process::exit(EXIT_INTERRUPTED);
     }
-    info!("Renaming {} to {}", key.0, newkey);
-    if dry_run {
-        return Ok(());
+    if summary.all_failed() {
+        std::process::exit(EXIT_TOTAL_FAILURE);
+    } else if summary.failed > 0 {
+        std::process::exit(EXIT_PARTIAL_FAILURE);
     }
+    Ok(())
+}
 
-    let mut grant_read_vec: Vec<String> = Vec::new();
-    let mut grant_read_acp_vec: Vec<String> = Vec::new();
-    let mut grant_write_acp_vec: Vec<String> = Vec::new();
-    let mut grant_full_control_vec: Vec<String> = Vec::new();
+/// Show the number of keys listed, bytes copied and requests in flight on the progress bar,
+/// whose position (the number of keys processed) is set as events arrive
+async fn update_progress_bar(progress_bar: ProgressBar, progress: Progress) {
+    progress_bar.set_style(
+        ProgressStyle::with_template(
+            "[{elapsed_precise}] {wide_bar} {pos}/{len} keys ({per_sec}, ETA {eta}) {msg}",
+        )
+        .unwrap(),
+    );
+    while !progress_bar.is_finished() {
+        progress_bar.set_length(progress.listed() as u64);
+        progress_bar.set_message(format!(
+            "{} copied, {} requests in flight",
+            HumanBytes(progress.bytes_copied()),
+            progress.requests_in_flight()
+        ));
+        tokio::time::delay_for(Duration::from_millis(200)).await;
+    }
+}
 
-    if !no_preserve_acl && canned_acl.is_none() {
-        let acl_request = GetObjectAclRequest {
-            bucket: (*bucket).to_string(),
-            key: key.0.clone(),
-            request_payer: None,
-            version_id: None,
-        };
-        let acl_response = client.get_object_acl(acl_request).await?;
-        debug!("{:?}", acl_response);
+/// Shut down gracefully on the first Ctrl-C / SIGTERM, and exit immediately on the second
+async fn handle_signals(shutdown: ShutdownHandle, tracker: Arc<Mutex<StageTracker>>) {
+    if let Err(e) = wait_for_signal().await {
+        error!("Could not listen for signals: {}", e);
+        return;
+    }
+    warn!("Interrupted: finishing renames in progress (interrupt again to exit immediately)");
+    shutdown.shutdown();
 
-        for grant in acl_response.grants.unwrap() {
-            let _ok_check = match grant.permission.as_deref() {
-                Some("READ") => {
-                    let grantee = grant.grantee.unwrap();
-                    grant_read_vec.push(generate_permission_grant(grantee)?);
-                    Ok(())
-                }
-                Some("WRITE") => {
-                    //TODO: No WRITE grant on CopyObjectRequest - is this controlled by bucket ACL?
-                    debug!(
-                        "Warning: WRITE access ignored for grantee: {:?} on key: {}",
-                        grant.grantee.unwrap(),
-                        &key.0
-                    );
-                    Ok(())
-                }
-                Some("READ_ACP") => {
-                    let grantee = grant.grantee.unwrap();
-                    grant_read_acp_vec.push(generate_permission_grant(grantee)?);
-                    Ok(())
-                }
-                Some("WRITE_ACP") => {
-                    let grantee = grant.grantee.unwrap();
-                    grant_write_acp_vec.push(generate_permission_grant(grantee)?);
-                    Ok(())
-                }
-                Some("FULL_CONTROL") => {
-                    let grantee = grant.grantee.unwrap();
-                    grant_full_control_vec.push(generate_permission_grant(grantee)?);
-                    Ok(())
-                }
-                Some(other) => Err(GranteeParseError::InvalidPermission {
-                    permission: String::from(other),
-                    grantee: grant.grantee.unwrap(),
-                }),
-                None => Err(GranteeParseError::MissingPermission {
-                    grantee: grant.grantee.unwrap(),
-                }),
-            }?;
-        }
+    if wait_for_signal().await.is_ok() {
+        error!("Interrupted again: exiting immediately");
+        report_incomplete_keys(&tracker.lock().unwrap());
+        std::process::exit(EXIT_INTERRUPTED);
     }
-    let copy_request = match no_preserve_properties {
-        false => {
-            let head_request = HeadObjectRequest {
-                bucket: (*bucket).to_string(),
-                if_match: None,
-                if_modified_since: None,
-                if_none_match: None,
-                if_unmodified_since: None,
-                key: key.0.clone(),
-                part_number: None,
-                range: None,
-                request_payer: None,
-                sse_customer_algorithm: None, // Seems we can get metadata for Copy without this
-                sse_customer_key: None,
-                sse_customer_key_md5: None,
-                version_id: None,
-            };
-            let head_result = client.head_object(head_request).await?;
-            CopyObjectRequest {
-                acl: canned_acl.map(|x| x.to_string()),
-                bucket: (*bucket).to_string(),
-                cache_control: head_result.cache_control,
-                content_disposition: head_result.content_disposition,
-                content_encoding: head_result.content_encoding,
-                content_language: head_result.content_language,
-                content_type: head_result.content_type,
-                copy_source: format!("{}/{}", bucket, key.0),
-                copy_source_if_match: None,
-                copy_source_if_modified_since: None,
-                copy_source_if_none_match: None,
-                copy_source_if_unmodified_since: None,
-                copy_source_sse_customer_algorithm: head_result.sse_customer_algorithm.clone(),
-                copy_source_sse_customer_key: None, //TODO
-                copy_source_sse_customer_key_md5: head_result.sse_customer_key_md5.clone(),
-                expires: head_result.expires,
-                grant_full_control: if !grant_full_control_vec.is_empty() {
-                    Some(grant_full_control_vec.join(", "))
-                } else {
-                    None
-                },
-                grant_read: if !grant_read_vec.is_empty() {
-                    Some(grant_read_vec.join(", "))
-                } else {
-                    None
-                },
-                grant_read_acp: if !grant_read_acp_vec.is_empty() {
-                    Some(grant_read_acp_vec.join(", "))
-                } else {
-                    None
-                },
-                grant_write_acp: if !grant_write_acp_vec.is_empty() {
-                    Some(grant_write_acp_vec.join(", "))
-                } else {
-                    None
-                },
-                key: String::from(newkey.to_owned()),
-                metadata: head_result.metadata,
-                metadata_directive: Some(String::from("REPLACE")), // Set to REPLACE due to
-                // multi-part copies: https://docs.aws.amazon.com/cli/latest/reference/s3/cp.html
-                object_lock_legal_hold_status: head_result.object_lock_legal_hold_status,
-                object_lock_mode: head_result.object_lock_mode,
-                object_lock_retain_until_date: head_result.object_lock_retain_until_date,
-                request_payer: head_result.request_charged, // TODO: Test me
-                sse_customer_algorithm: head_result.sse_customer_algorithm.clone(),
-                sse_customer_key: None, // TODO
-                sse_customer_key_md5: head_result.sse_customer_key_md5.clone(),
-                ssekms_encryption_context: None, // TODO
-                ssekms_key_id: head_result.ssekms_key_id,
-                server_side_encryption: head_result.server_side_encryption,
-                storage_class: key.1.clone(),
-                tagging: None, // tagging_directive should cover this anyway
-                tagging_directive: Some(String::from("COPY")),
-                website_redirect_location: head_result.website_redirect_location,
-            }
-        }
-        true => CopyObjectRequest {
-            acl: canned_acl.map(|x| x.to_string()),
-            bucket: (*bucket).to_string(),
-            cache_control: None,
-            content_disposition: None,
-            content_encoding: None,
-            content_language: None,
-            content_type: None,
-            copy_source: format!("{}/{}", bucket, key.0),
-            copy_source_if_match: None,
-            copy_source_if_modified_since: None,
-            copy_source_if_none_match: None,
-            copy_source_if_unmodified_since: None,
-            copy_source_sse_customer_algorithm: None,
-            copy_source_sse_customer_key: None,
-            copy_source_sse_customer_key_md5: None,
-            expires: None,
-            grant_full_control: if !grant_full_control_vec.is_empty() {
-                Some(grant_full_control_vec.join(", "))
-            } else {
-                None
-            },
-            grant_read: if !grant_read_vec.is_empty() {
-                Some(grant_read_vec.join(", "))
-            } else {
-                None
-            },
-            grant_read_acp: if !grant_read_acp_vec.is_empty() {
-                Some(grant_read_acp_vec.join(", "))
-            } else {
-                None
-            },
-            grant_write_acp: if !grant_write_acp_vec.is_empty() {
-                Some(grant_write_acp_vec.join(", "))
-            } else {
-                None
-            },
-            key: String::from(newkey.to_owned()),
-            metadata: None,
-            metadata_directive: Some(String::from("COPY")),
-            object_lock_legal_hold_status: None,
-            object_lock_mode: None,
-            object_lock_retain_until_date: None,
-            request_payer: None,
-            sse_customer_algorithm: None,
-            sse_customer_key: None,
-            sse_customer_key_md5: None,
-            ssekms_encryption_context: None,
-            ssekms_key_id: None,
-            server_side_encryption: None,
-            storage_class: key.1.clone(),
-            tagging: None,
-            tagging_directive: Some(String::from("COPY")),
-            website_redirect_location: None,
-        },
-    };
+}
 
-    let _copy_response: WrappedCopyRequest = WrappedCopyRequest::new(
-        client.clone(),
-        copy_request,
-        key.0.clone(),
-        destructor_futures.clone(),
-    )
-    .await?;
+#[cfg(unix)]
+async fn wait_for_signal() -> std::io::Result<()> {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
+    }
+}
 
-    Ok(())
+#[cfg(not(unix))]
+async fn wait_for_signal() -> std::io::Result<()> {
+    tokio::signal::ctrl_c().await
 }
 
-/// Convert a Grantee object to a grant String to use in the CopyObjectRequest
-fn generate_permission_grant(grantee: Grantee) -> Result<String, GranteeParseError> {
-    if let Some(uri) = grantee.uri {
-        return Ok(format!("uri=\"{}\"", uri));
-    }
-    if let Some(id) = grantee.id {
-        return Ok(format!("id=\"{}\"", id));
+/// Print the keys which were left in an intermediate state (i.e. not fully renamed)
+fn report_incomplete_keys(tracker: &StageTracker) {
+    for key in tracker.in_progress() {
+        match key.stage {
+            RenameStage::Planned => warn!(
+                "Rename of {} to {} was interrupted, a copy may exist at {}",
+                key.source, key.target, key.target
+            ),
+            _ => warn!(
+                "Rename of {} to {} was interrupted after the key was {}, it exists at both",
+                key.source, key.target, key.stage
+            ),
+        }
     }
-    if let Some(email) = grantee.email_address {
-        return Ok(format!("emailAddress=\"{}\"", email));
+    for key in tracker.failed_after_copy() {
+        warn!(
+            "Rename of {} to {} failed after the key was {}, it exists at both",
+            key.source, key.target, key.stage
+        );
     }
-    Err(GranteeParseError::NoValidID { grantee })
 }
 
 /// Setup the logger.
@@ -451,26 +235,150 @@ fn generate_permission_grant(grantee: Grantee) -> Result<String, GranteeParseErr
 /// - If `verbose == false` and `quiet == false` then `Info`
 /// - If `verbose == true`, then `Debug`
 /// - If `quite == true`, then `Warn`
-fn setup_logger(verbose: bool, quiet: bool) -> Result<(), fern::InitError> {
-    let log_level = if !verbose && !quiet {
+///
+/// Warnings and errors are written to stderr, and other messages to stdout, in both cases above
+/// `progress_bar` if it is displayed. Every message is also appended to the `--log-file`, if
+/// given.
+fn setup_logger(opt: &args::App, progress_bar: ProgressBar) -> Result<(), fern::InitError> {
+    let log_level = if !opt.verbose && !opt.quiet {
         log::LevelFilter::Info
-    } else if verbose {
+    } else if opt.verbose {
         log::LevelFilter::Debug
     } else {
         log::LevelFilter::Warn
     };
-    fern::Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "{}[{}][{}] {}",
-                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
-                record.target(),
-                record.level(),
-                message
-            ))
-        })
-        .level(log_level)
-        .chain(std::io::stdout())
-        .apply()?;
+    let stderr_progress_bar = progress_bar.clone();
+    let mut dispatch = split_output(
+        fern::Dispatch::new()
+            .format(match opt.log_format {
+                LogFormat::Text => format_text,
+                LogFormat::Json => format_json,
+            })
+            .level(log_level),
+        fern::Output::call(move |record| progress_bar.suspend(|| println!("{}", record.args()))),
+        fern::Output::call(move |record| {
+            stderr_progress_bar.suspend(|| eprintln!("{}", record.args()))
+        }),
+    );
+    if let Some(path) = &opt.log_file {
+        dispatch = dispatch.chain(fern::log_file(path)?);
+    }
+    dispatch.apply()?;
     Ok(())
 }
+
+/// Send warnings and errors to `stderr`, and other messages (e.g. the "Renaming ... to ..."
+/// listing) to `stdout`
+fn split_output(
+    dispatch: fern::Dispatch,
+    stdout: fern::Output,
+    stderr: fern::Output,
+) -> fern::Dispatch {
+    dispatch
+        .chain(
+            fern::Dispatch::new()
+                .filter(|metadata| metadata.level() > log::Level::Warn)
+                .chain(stdout),
+        )
+        .chain(
+            fern::Dispatch::new()
+                .filter(|metadata| metadata.level() <= log::Level::Warn)
+                .chain(stderr),
+        )
+}
+
+fn format_text(out: fern::FormatCallback, message: &fmt::Arguments, record: &log::Record) {
+    out.finish(format_args!(
+        "{}[{}][{}] {}",
+        chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
+        record.target(),
+        record.level(),
+        message
+    ))
+}
+
+/// Format a message as a line of JSON, with the structured fields of the message (e.g. key and
+/// new_key) alongside the timestamp, level, target and message
+fn format_json(out: fern::FormatCallback, message: &fmt::Arguments, record: &log::Record) {
+    struct Fields<'a>(&'a mut serde_json::Map<String, serde_json::Value>);
+
+    impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+            let value = serde_json::to_value(value).map_err(kv::Error::boxed)?;
+            self.0.insert(key.to_string(), value);
+            Ok(())
+        }
+    }
+
+    let mut line = serde_json::Map::new();
+    line.insert(
+        String::from("timestamp"),
+        chrono::Utc::now()
+            .to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+            .into(),
+    );
+    line.insert(String::from("level"), record.level().to_string().into());
+    line.insert(String::from("target"), record.target().into());
+    line.insert(String::from("message"), message.to_string().into());
+    let _ = record.key_values().visit(&mut Fields(&mut line));
+    out.finish(format_args!("{}", serde_json::Value::Object(line)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::{Level, Log, Record};
+    use std::sync::mpsc;
+
+    #[test]
+    fn json_logs_split_between_stdout_and_stderr() {
+        let (stdout, stdout_lines) = mpsc::channel();
+        let (stderr, stderr_lines) = mpsc::channel();
+        let (_, logger) = split_output(
+            fern::Dispatch::new().format(format_json),
+            stdout.into(),
+            stderr.into(),
+        )
+        .into_log();
+
+        logger.log(
+            &Record::builder()
+                .level(Level::Info)
+                .args(format_args!("Renaming a.txt to b.txt"))
+                .key_values(&[
+                    ("key", "a.txt"),
+                    ("new_key", "b.txt"),
+                    ("action", "renaming"),
+                ])
+                .build(),
+        );
+        logger.log(
+            &Record::builder()
+                .level(Level::Error)
+                .args(format_args!("Failed to rename c.txt: Access Denied"))
+                .key_values(&[
+                    ("key", "c.txt"),
+                    ("action", "failed"),
+                    ("error", "Access Denied"),
+                    ("request_id", "0123456789ABCDEF"),
+                ])
+                .build(),
+        );
+
+        let parse = |line: String| -> serde_json::Value { serde_json::from_str(&line).unwrap() };
+        let stdout: Vec<serde_json::Value> = stdout_lines.try_iter().map(parse).collect();
+        let stderr: Vec<serde_json::Value> = stderr_lines.try_iter().map(parse).collect();
+        assert_eq!(stdout.len(), 1);
+        assert_eq!(stdout[0]["level"], "INFO");
+        assert_eq!(stdout[0]["message"], "Renaming a.txt to b.txt");
+        assert_eq!(stdout[0]["key"], "a.txt");
+        assert_eq!(stdout[0]["new_key"], "b.txt");
+        assert_eq!(stdout[0]["action"], "renaming");
+        assert_eq!(stderr.len(), 1);
+        assert_eq!(stderr[0]["level"], "ERROR");
+        assert_eq!(stderr[0]["key"], "c.txt");
+        assert_eq!(stderr[0]["action"], "failed");
+        assert_eq!(stderr[0]["error"], "Access Denied");
+        assert_eq!(stderr[0]["request_id"], "0123456789ABCDEF");
+    }
+}
@@ -0,0 +1,91 @@
+use super::errors::ManifestError;
+use super::jsonl::{read_json_lines, JsonLinesWriter, ReadLinesError};
+use super::renamer::PlannedRename;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// A completed rename, as recorded in the manifest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub bucket: String,
+    /// The original key (which has been deleted)
+    pub source: String,
+    /// The new key
+    pub target: String,
+    /// ETag of the object at the new key
+    pub e_tag: Option<String>,
+    /// Version ID of the object at the new key, if the bucket is versioned
+    pub version_id: Option<String>,
+}
+
+/// Record of every completed rename, which can be reversed with `s3rename undo`
+///
+/// Each rename is written as a line of JSON once the source key has been deleted.
+#[derive(Debug)]
+pub struct Manifest {
+    writer: JsonLinesWriter,
+}
+
+impl Manifest {
+    /// Create a new manifest, overwriting any existing file
+    pub fn create(path: &Path) -> Result<Self, ManifestError> {
+        let writer = JsonLinesWriter::create(path).map_err(|error| ManifestError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        Ok(Manifest { writer })
+    }
+
+    pub fn record(&self, entry: &ManifestEntry) -> Result<(), ManifestError> {
+        self.writer.write(entry).map_err(|error| ManifestError::Io {
+            path: self.writer.path().to_path_buf(),
+            error,
+        })
+    }
+
+    /// Read all entries of a manifest
+    pub fn read(path: &Path) -> Result<Vec<ManifestEntry>, ManifestError> {
+        read_json_lines(path).map_err(|error| match error {
+            ReadLinesError::Io(error) => ManifestError::Io {
+                path: path.to_path_buf(),
+                error,
+            },
+            ReadLinesError::Parse { line, error } => ManifestError::Parse {
+                path: path.to_path_buf(),
+                line,
+                error,
+            },
+        })
+    }
+
+    /// The bucket and renames which reverse the entries of the manifest at `path`
+    ///
+    /// Renames are returned in the reverse order to which they were made, and each is only carried
+    /// out if the object at the new key still has the recorded ETag.
+    pub fn undo_renames(path: &Path) -> Result<(String, Vec<PlannedRename>), ManifestError> {
+        let entries = Self::read(path)?;
+        let bucket = match entries.first() {
+            Some(entry) => entry.bucket.clone(),
+            None => {
+                return Err(ManifestError::Empty {
+                    path: path.to_path_buf(),
+                })
+            }
+        };
+        if entries.iter().any(|x| x.bucket != bucket) {
+            return Err(ManifestError::MultipleBuckets {
+                path: path.to_path_buf(),
+            });
+        }
+        let renames = entries
+            .into_iter()
+            .rev()
+            .map(|x| PlannedRename {
+                source: x.target,
+                target: x.source,
+                e_tag: x.e_tag,
+            })
+            .collect();
+        Ok((bucket, renames))
+    }
+}
@@ -0,0 +1,71 @@
+use super::errors::MappingError;
+use super::jsonl::{read_json_lines_strict, ReadLinesError};
+use super::renamer::PlannedRename;
+use std::fs::File;
+use std::path::Path;
+
+/// Read `source,target` pairs from a mapping file
+///
+/// The format is chosen by the file extension: comma separated (.csv), tab separated (.tsv) or
+/// one JSON object with "source" and "target" fields per line (.jsonl or .ndjson). A first row of
+/// `source,target` in a CSV or TSV file is treated as a header and skipped.
+pub fn read_mapping(path: &Path) -> Result<Vec<PlannedRename>, MappingError> {
+    let extension = path
+        .extension()
+        .and_then(|x| x.to_str())
+        .map(|x| x.to_lowercase());
+    match extension.as_deref() {
+        Some("csv") => read_delimited(path, b','),
+        Some("tsv") => read_delimited(path, b'\t'),
+        Some("jsonl") | Some("ndjson") => read_json_lines(path),
+        _ => Err(MappingError::UnknownFormat {
+            path: path.to_path_buf(),
+        }),
+    }
+}
+
+fn read_delimited(path: &Path, delimiter: u8) -> Result<Vec<PlannedRename>, MappingError> {
+    let file = File::open(path).map_err(|error| MappingError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(delimiter)
+        .from_reader(file);
+    let mut renames = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let invalid = |error: String| MappingError::Parse {
+            path: path.to_path_buf(),
+            line: i + 1,
+            error,
+        };
+        let record = record.map_err(|x| invalid(x.to_string()))?;
+        match (record.get(0), record.get(1), record.len()) {
+            (Some("source"), Some("target"), 2) if i == 0 => {}
+            (Some(source), Some(target), 2) if !source.is_empty() && !target.is_empty() => {
+                renames.push(PlannedRename {
+                    source: String::from(source),
+                    target: String::from(target),
+                    e_tag: None,
+                });
+            }
+            _ => return Err(invalid(String::from("expected a source and target key"))),
+        }
+    }
+    Ok(renames)
+}
+
+fn read_json_lines(path: &Path) -> Result<Vec<PlannedRename>, MappingError> {
+    read_json_lines_strict(path).map_err(|error| match error {
+        ReadLinesError::Io(error) => MappingError::Io {
+            path: path.to_path_buf(),
+            error,
+        },
+        ReadLinesError::Parse { line, error } => MappingError::Parse {
+            path: path.to_path_buf(),
+            line,
+            error: error.to_string(),
+        },
+    })
+}
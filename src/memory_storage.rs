@@ -0,0 +1,661 @@
+use super::storage::Storage;
+use async_trait::async_trait;
+use bytes::Bytes;
+use rusoto_core::request::BufferedHttpResponse;
+use rusoto_core::ByteStream;
+use rusoto_core::RusotoError;
+use rusoto_s3::{AbortMultipartUploadError, AbortMultipartUploadOutput};
+use rusoto_s3::{AbortMultipartUploadRequest, CompleteMultipartUploadError};
+use rusoto_s3::{CommonPrefix, Grant, Grantee, HeadObjectOutput, Object};
+use rusoto_s3::{CompleteMultipartUploadOutput, CompleteMultipartUploadRequest};
+use rusoto_s3::{CopyObjectError, CopyObjectOutput, CopyObjectRequest, CopyObjectResult};
+use rusoto_s3::{CopyPartResult, CreateMultipartUploadError, CreateMultipartUploadOutput};
+use rusoto_s3::{CreateMultipartUploadRequest, UploadPartCopyError};
+use rusoto_s3::{DeleteObjectError, DeleteObjectOutput, DeleteObjectRequest};
+use rusoto_s3::{GetObjectAclError, GetObjectAclOutput, GetObjectAclRequest};
+use rusoto_s3::{GetObjectError, GetObjectOutput, GetObjectRequest};
+use rusoto_s3::{GetObjectTaggingError, GetObjectTaggingOutput, GetObjectTaggingRequest, Tag};
+use rusoto_s3::{HeadObjectError, HeadObjectRequest};
+use rusoto_s3::{ListObjectsV2Error, ListObjectsV2Output, ListObjectsV2Request};
+use rusoto_s3::{UploadPartCopyOutput, UploadPartCopyRequest};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+/// An object held by `MemoryStorage`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemoryObject {
+    /// Properties returned by HeadObject (size, metadata, encryption, storage class, etc.)
+    pub head: HeadObjectOutput,
+    /// Grants returned by GetObjectAcl
+    pub grants: Vec<Grant>,
+    /// Tags returned by GetObjectTagging (copied by CopyObject, but not by multipart copies)
+    pub tags: Vec<Tag>,
+    /// Content returned by GetObject (copied by CopyObject, but not by multipart copies)
+    pub body: Vec<u8>,
+}
+
+/// A request made against `MemoryStorage`
+#[derive(Clone, Debug, PartialEq)]
+pub enum StorageCall {
+    ListObjectsV2(ListObjectsV2Request),
+    HeadObject(HeadObjectRequest),
+    GetObjectAcl(GetObjectAclRequest),
+    GetObjectTagging(GetObjectTaggingRequest),
+    GetObject(GetObjectRequest),
+    CopyObject(Box<CopyObjectRequest>),
+    DeleteObject(DeleteObjectRequest),
+    CreateMultipartUpload(Box<CreateMultipartUploadRequest>),
+    UploadPartCopy(Box<UploadPartCopyRequest>),
+    CompleteMultipartUpload(CompleteMultipartUploadRequest),
+    AbortMultipartUpload(AbortMultipartUploadRequest),
+}
+
+impl StorageCall {
+    /// Name of the S3 operation
+    pub fn operation(&self) -> &'static str {
+        match self {
+            StorageCall::ListObjectsV2(_) => "ListObjectsV2",
+            StorageCall::HeadObject(_) => "HeadObject",
+            StorageCall::GetObjectAcl(_) => "GetObjectAcl",
+            StorageCall::GetObjectTagging(_) => "GetObjectTagging",
+            StorageCall::GetObject(_) => "GetObject",
+            StorageCall::CopyObject(_) => "CopyObject",
+            StorageCall::DeleteObject(_) => "DeleteObject",
+            StorageCall::CreateMultipartUpload(_) => "CreateMultipartUpload",
+            StorageCall::UploadPartCopy(_) => "UploadPartCopy",
+            StorageCall::CompleteMultipartUpload(_) => "CompleteMultipartUpload",
+            StorageCall::AbortMultipartUpload(_) => "AbortMultipartUpload",
+        }
+    }
+
+    /// Key the request refers to (the prefix for ListObjectsV2)
+    pub fn key(&self) -> &str {
+        match self {
+            StorageCall::ListObjectsV2(x) => x.prefix.as_deref().unwrap_or_default(),
+            StorageCall::HeadObject(x) => &x.key,
+            StorageCall::GetObjectAcl(x) => &x.key,
+            StorageCall::GetObjectTagging(x) => &x.key,
+            StorageCall::GetObject(x) => &x.key,
+            StorageCall::CopyObject(x) => &x.key,
+            StorageCall::DeleteObject(x) => &x.key,
+            StorageCall::CreateMultipartUpload(x) => &x.key,
+            StorageCall::UploadPartCopy(x) => &x.key,
+            StorageCall::CompleteMultipartUpload(x) => &x.key,
+            StorageCall::AbortMultipartUpload(x) => &x.key,
+        }
+    }
+}
+
+/// A multipart upload which has been created but not completed or aborted
+#[derive(Debug)]
+struct MemoryUpload {
+    request: CreateMultipartUploadRequest,
+    /// Size in bytes of each uploaded part, by part number
+    parts: BTreeMap<i64, i64>,
+}
+
+/// In-memory `Storage` backend which records every request made against it
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    objects: Mutex<BTreeMap<(String, String), MemoryObject>>,
+    calls: Mutex<Vec<StorageCall>>,
+    uploads: Mutex<HashMap<String, MemoryUpload>>,
+    /// Injected failures by operation and key
+    failures: Mutex<HashMap<(&'static str, String), InjectedFailure>>,
+}
+
+/// Failure injected with `MemoryStorage::fail_requests`
+#[derive(Debug)]
+struct InjectedFailure {
+    remaining: usize,
+    status: u16,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put_object(&self, bucket: &str, key: &str, object: MemoryObject) {
+        self.objects
+            .lock()
+            .unwrap()
+            .insert((String::from(bucket), String::from(key)), object);
+    }
+
+    pub fn get_object(&self, bucket: &str, key: &str) -> Option<MemoryObject> {
+        self.objects
+            .lock()
+            .unwrap()
+            .get(&(String::from(bucket), String::from(key)))
+            .cloned()
+    }
+
+    /// All keys currently stored in the given bucket, in lexicographic order
+    pub fn keys(&self, bucket: &str) -> Vec<String> {
+        self.objects
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|(b, _)| b == bucket)
+            .map(|(_, k)| k.clone())
+            .collect()
+    }
+
+    /// All requests made so far, in the order they were received
+    pub fn calls(&self) -> Vec<StorageCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Upload IDs of multipart uploads which have not been completed or aborted
+    pub fn pending_uploads(&self) -> Vec<String> {
+        self.uploads.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Make the next `count` requests of `operation` (e.g. "DeleteObject") for `key` fail with
+    /// the given HTTP status code
+    pub fn fail_requests(&self, operation: &'static str, key: &str, count: usize, status: u16) {
+        self.failures.lock().unwrap().insert(
+            (operation, String::from(key)),
+            InjectedFailure {
+                remaining: count,
+                status,
+            },
+        );
+    }
+
+    /// Record a request, returning an error if a failure was injected for it
+    #[allow(clippy::result_large_err)] // Same error type as the Storage trait
+    fn record<E>(&self, call: StorageCall) -> Result<(), RusotoError<E>> {
+        let failure_key = (call.operation(), String::from(call.key()));
+        let mut calls = self.calls.lock().unwrap();
+        calls.push(call);
+
+        let mut failures = self.failures.lock().unwrap();
+        match failures.get_mut(&failure_key) {
+            Some(failure) if failure.remaining > 0 => {
+                failure.remaining -= 1;
+                let mut error =
+                    error_response(http::StatusCode::from_u16(failure.status).unwrap_or_default());
+                // Request IDs are 16 hex digits, here numbering the requests made
+                if let RusotoError::Unknown(response) = &mut error {
+                    response
+                        .headers
+                        .insert("x-amz-request-id", format!("{:016X}", calls.len()));
+                }
+                Err(error)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Error for requests which are invalid rather than for missing keys
+fn bad_request<E>() -> RusotoError<E> {
+    error_response(http::StatusCode::BAD_REQUEST)
+}
+
+/// Parse a `bytes=start-end` range into its length, checking it lies within `size`
+fn range_length(range: &str, size: i64) -> Option<i64> {
+    let mut bounds = range.strip_prefix("bytes=")?.splitn(2, '-');
+    let start: i64 = bounds.next()?.parse().ok()?;
+    let end: i64 = bounds.next()?.parse().ok()?;
+    if start > end || end >= size {
+        return None;
+    }
+    Some(end - start + 1)
+}
+
+/// Error with an empty response body, as S3 returns for HEAD requests
+fn error_response<E>(status: http::StatusCode) -> RusotoError<E> {
+    RusotoError::Unknown(BufferedHttpResponse {
+        status,
+        body: Bytes::new(),
+        headers: http::HeaderMap::default(),
+    })
+}
+
+/// Error matching the response S3 returns when a copy source no longer has the expected ETag
+fn precondition_failed<E>() -> RusotoError<E> {
+    error_response(http::StatusCode::PRECONDITION_FAILED)
+}
+
+/// Error matching the bare 404 response S3 returns for missing keys
+fn not_found<E>() -> RusotoError<E> {
+    error_response(http::StatusCode::NOT_FOUND)
+}
+
+/// Parse a URL-encoded tagging header (e.g. "team=analytics&env=prod") into Tag objects
+fn parse_tags(header: &Option<String>) -> Vec<Tag> {
+    let header = match header {
+        Some(x) => x,
+        None => return Vec::new(),
+    };
+    header
+        .split('&')
+        .filter_map(|tag| {
+            let (key, value) = tag.split_once('=')?;
+            let decode = |x: &str| {
+                percent_encoding::percent_decode_str(x)
+                    .decode_utf8_lossy()
+                    .into_owned()
+            };
+            Some(Tag {
+                key: decode(key),
+                value: decode(value),
+            })
+        })
+        .collect()
+}
+
+/// Parse a grant header (as built for CopyObjectRequest) back into Grant objects
+fn parse_grants(header: &Option<String>, permission: &str) -> Vec<Grant> {
+    let header = match header {
+        Some(x) => x,
+        None => return Vec::new(),
+    };
+    header
+        .split(", ")
+        .filter_map(|grant| {
+            let mut parts = grant.splitn(2, '=');
+            let kind = parts.next()?;
+            let value = parts.next()?.trim_matches('"').to_string();
+            let grantee = match kind {
+                "uri" => Grantee {
+                    type_: String::from("Group"),
+                    uri: Some(value),
+                    ..Default::default()
+                },
+                "id" => Grantee {
+                    type_: String::from("CanonicalUser"),
+                    id: Some(value),
+                    ..Default::default()
+                },
+                "emailAddress" => Grantee {
+                    type_: String::from("AmazonCustomerByEmail"),
+                    email_address: Some(value),
+                    ..Default::default()
+                },
+                _ => return None,
+            };
+            Some(Grant {
+                grantee: Some(grantee),
+                permission: Some(String::from(permission)),
+            })
+        })
+        .collect()
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn list_objects_v2(
+        &self,
+        input: ListObjectsV2Request,
+    ) -> Result<ListObjectsV2Output, RusotoError<ListObjectsV2Error>> {
+        self.record(StorageCall::ListObjectsV2(input.clone()))?;
+        let max_keys = input.max_keys.unwrap_or(1000) as usize;
+        let prefix = input.prefix.clone().unwrap_or_default();
+        let start_after = input
+            .continuation_token
+            .clone()
+            .or_else(|| input.start_after.clone());
+
+        let delimiter = input.delimiter.clone().filter(|x| !x.is_empty());
+
+        let objects = self.objects.lock().unwrap();
+        let matching = objects
+            .iter()
+            .filter(|((bucket, key), _)| *bucket == input.bucket && key.starts_with(&prefix))
+            .filter(|((_, key), _)| match &start_after {
+                // Continuing after a common prefix skips every key under it
+                Some(after)
+                    if delimiter
+                        .as_ref()
+                        .is_some_and(|x| after.ends_with(x.as_str())) =>
+                {
+                    key.as_str() > after.as_str() && !key.starts_with(after.as_str())
+                }
+                Some(after) => key > after,
+                None => true,
+            });
+
+        // Keys containing the delimiter after the prefix are rolled up into a common prefix,
+        // which counts as a single entry towards `max_keys`
+        let mut contents: Vec<Object> = Vec::new();
+        let mut common_prefixes: Vec<CommonPrefix> = Vec::new();
+        let mut last_entry: Option<String> = None;
+        let mut is_truncated = false;
+        for ((_, key), object) in matching {
+            let common_prefix = delimiter.as_ref().and_then(|delimiter| {
+                key[prefix.len()..]
+                    .find(delimiter.as_str())
+                    .map(|i| String::from(&key[..prefix.len() + i + delimiter.len()]))
+            });
+            if common_prefix.is_some() && common_prefix == last_entry {
+                continue;
+            }
+            if contents.len() + common_prefixes.len() == max_keys {
+                is_truncated = true;
+                break;
+            }
+            match common_prefix {
+                Some(common_prefix) => {
+                    last_entry = Some(common_prefix.clone());
+                    common_prefixes.push(CommonPrefix {
+                        prefix: Some(common_prefix),
+                    });
+                }
+                None => {
+                    last_entry = Some(key.clone());
+                    contents.push(Object {
+                        e_tag: object.head.e_tag.clone(),
+                        key: Some(key.clone()),
+                        last_modified: object.head.last_modified.clone(),
+                        owner: None,
+                        size: object.head.content_length,
+                        storage_class: object.head.storage_class.clone(),
+                    });
+                }
+            }
+        }
+
+        Ok(ListObjectsV2Output {
+            key_count: Some((contents.len() + common_prefixes.len()) as i64),
+            next_continuation_token: if is_truncated { last_entry } else { None },
+            contents: if contents.is_empty() {
+                None
+            } else {
+                Some(contents)
+            },
+            common_prefixes: if common_prefixes.is_empty() {
+                None
+            } else {
+                Some(common_prefixes)
+            },
+            delimiter: input.delimiter,
+            continuation_token: input.continuation_token,
+            is_truncated: Some(is_truncated),
+            max_keys: Some(max_keys as i64),
+            name: Some(input.bucket),
+            prefix: input.prefix,
+            start_after: input.start_after,
+            ..Default::default()
+        })
+    }
+
+    async fn head_object(
+        &self,
+        input: HeadObjectRequest,
+    ) -> Result<HeadObjectOutput, RusotoError<HeadObjectError>> {
+        self.record(StorageCall::HeadObject(input.clone()))?;
+        self.get_object(&input.bucket, &input.key)
+            .map(|x| x.head)
+            .ok_or_else(not_found)
+    }
+
+    async fn get_object_acl(
+        &self,
+        input: GetObjectAclRequest,
+    ) -> Result<GetObjectAclOutput, RusotoError<GetObjectAclError>> {
+        self.record(StorageCall::GetObjectAcl(input.clone()))?;
+        self.get_object(&input.bucket, &input.key)
+            .map(|x| GetObjectAclOutput {
+                grants: Some(x.grants),
+                ..Default::default()
+            })
+            .ok_or_else(not_found)
+    }
+
+    async fn get_object_tagging(
+        &self,
+        input: GetObjectTaggingRequest,
+    ) -> Result<GetObjectTaggingOutput, RusotoError<GetObjectTaggingError>> {
+        self.record(StorageCall::GetObjectTagging(input.clone()))?;
+        self.get_object(&input.bucket, &input.key)
+            .map(|x| GetObjectTaggingOutput {
+                tag_set: x.tags,
+                ..Default::default()
+            })
+            .ok_or_else(not_found)
+    }
+
+    async fn get_object(
+        &self,
+        input: GetObjectRequest,
+    ) -> Result<GetObjectOutput, RusotoError<GetObjectError>> {
+        self.record(StorageCall::GetObject(input.clone()))?;
+        self.get_object(&input.bucket, &input.key)
+            .map(|x| GetObjectOutput {
+                content_length: Some(x.body.len() as i64),
+                body: Some(ByteStream::from(x.body)),
+                e_tag: x.head.e_tag,
+                ..Default::default()
+            })
+            .ok_or_else(not_found)
+    }
+
+    async fn copy_object(
+        &self,
+        input: CopyObjectRequest,
+    ) -> Result<CopyObjectOutput, RusotoError<CopyObjectError>> {
+        self.record(StorageCall::CopyObject(Box::new(input.clone())))?;
+        let mut source = input.copy_source.splitn(2, '/');
+        let source_bucket = source.next().unwrap_or_default();
+        let source_key = source.next().unwrap_or_default();
+        let source = self
+            .get_object(source_bucket, source_key)
+            .ok_or_else(not_found)?;
+        if input.copy_source_if_match.is_some() && input.copy_source_if_match != source.head.e_tag {
+            return Err(precondition_failed());
+        }
+
+        let head = match input.metadata_directive.as_deref() {
+            Some("REPLACE") => HeadObjectOutput {
+                cache_control: input.cache_control.clone(),
+                content_disposition: input.content_disposition.clone(),
+                content_encoding: input.content_encoding.clone(),
+                content_language: input.content_language.clone(),
+                content_length: source.head.content_length,
+                content_type: input.content_type.clone(),
+                e_tag: source.head.e_tag.clone(),
+                expires: input.expires.clone(),
+                last_modified: source.head.last_modified.clone(),
+                metadata: input.metadata.clone(),
+                object_lock_legal_hold_status: input.object_lock_legal_hold_status.clone(),
+                object_lock_mode: input.object_lock_mode.clone(),
+                object_lock_retain_until_date: input.object_lock_retain_until_date.clone(),
+                sse_customer_algorithm: input.sse_customer_algorithm.clone(),
+                sse_customer_key_md5: input.sse_customer_key_md5.clone(),
+                ssekms_key_id: input.ssekms_key_id.clone(),
+                server_side_encryption: input.server_side_encryption.clone(),
+                storage_class: input.storage_class.clone(),
+                website_redirect_location: input.website_redirect_location.clone(),
+                ..Default::default()
+            },
+            _ => HeadObjectOutput {
+                storage_class: input.storage_class.clone(),
+                ..source.head.clone()
+            },
+        };
+
+        let grants = [
+            parse_grants(&input.grant_read, "READ"),
+            parse_grants(&input.grant_read_acp, "READ_ACP"),
+            parse_grants(&input.grant_write_acp, "WRITE_ACP"),
+            parse_grants(&input.grant_full_control, "FULL_CONTROL"),
+        ]
+        .concat();
+
+        let e_tag = head.e_tag.clone();
+        let last_modified = head.last_modified.clone();
+        let tags = match input.tagging_directive.as_deref() {
+            Some("REPLACE") => parse_tags(&input.tagging),
+            _ => source.tags,
+        };
+        let object = MemoryObject {
+            head,
+            grants,
+            tags,
+            body: source.body,
+        };
+        self.put_object(&input.bucket, &input.key, object);
+
+        Ok(CopyObjectOutput {
+            copy_object_result: Some(CopyObjectResult {
+                e_tag,
+                last_modified,
+            }),
+            ..Default::default()
+        })
+    }
+
+    async fn delete_object(
+        &self,
+        input: DeleteObjectRequest,
+    ) -> Result<DeleteObjectOutput, RusotoError<DeleteObjectError>> {
+        self.record(StorageCall::DeleteObject(input.clone()))?;
+        self.objects
+            .lock()
+            .unwrap()
+            .remove(&(input.bucket.clone(), input.key.clone()));
+        Ok(DeleteObjectOutput::default())
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        input: CreateMultipartUploadRequest,
+    ) -> Result<CreateMultipartUploadOutput, RusotoError<CreateMultipartUploadError>> {
+        self.record(StorageCall::CreateMultipartUpload(Box::new(input.clone())))?;
+        let mut uploads = self.uploads.lock().unwrap();
+        let upload_id = format!("upload-{}", self.calls.lock().unwrap().len());
+        let output = CreateMultipartUploadOutput {
+            bucket: Some(input.bucket.clone()),
+            key: Some(input.key.clone()),
+            upload_id: Some(upload_id.clone()),
+            ..Default::default()
+        };
+        uploads.insert(
+            upload_id,
+            MemoryUpload {
+                request: input,
+                parts: BTreeMap::new(),
+            },
+        );
+        Ok(output)
+    }
+
+    async fn upload_part_copy(
+        &self,
+        input: UploadPartCopyRequest,
+    ) -> Result<UploadPartCopyOutput, RusotoError<UploadPartCopyError>> {
+        self.record(StorageCall::UploadPartCopy(Box::new(input.clone())))?;
+        let mut source = input.copy_source.splitn(2, '/');
+        let source_bucket = source.next().unwrap_or_default();
+        let source_key = source.next().unwrap_or_default();
+        let source = self
+            .get_object(source_bucket, source_key)
+            .ok_or_else(not_found)?;
+        if input.copy_source_if_match.is_some() && input.copy_source_if_match != source.head.e_tag {
+            return Err(precondition_failed());
+        }
+        let size = source.head.content_length.unwrap_or_default();
+        let length = match &input.copy_source_range {
+            Some(range) => range_length(range, size).ok_or_else(bad_request)?,
+            None => size,
+        };
+
+        let mut uploads = self.uploads.lock().unwrap();
+        let upload = uploads.get_mut(&input.upload_id).ok_or_else(not_found)?;
+        upload.parts.insert(input.part_number, length);
+        Ok(UploadPartCopyOutput {
+            copy_part_result: Some(CopyPartResult {
+                e_tag: Some(format!("\"{}-{}\"", input.upload_id, input.part_number)),
+                last_modified: None,
+            }),
+            ..Default::default()
+        })
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        input: CompleteMultipartUploadRequest,
+    ) -> Result<CompleteMultipartUploadOutput, RusotoError<CompleteMultipartUploadError>> {
+        self.record(StorageCall::CompleteMultipartUpload(input.clone()))?;
+        let upload = self
+            .uploads
+            .lock()
+            .unwrap()
+            .remove(&input.upload_id)
+            .ok_or_else(not_found)?;
+        let completed: Vec<i64> = input
+            .multipart_upload
+            .and_then(|x| x.parts)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|x| x.part_number)
+            .collect();
+        let uploaded: Vec<i64> = upload.parts.keys().cloned().collect();
+        if completed != uploaded {
+            return Err(bad_request());
+        }
+
+        let request = upload.request;
+        let e_tag = Some(format!("\"{}-{}\"", input.upload_id, completed.len()));
+        let head = HeadObjectOutput {
+            cache_control: request.cache_control,
+            content_disposition: request.content_disposition,
+            content_encoding: request.content_encoding,
+            content_language: request.content_language,
+            content_length: Some(upload.parts.values().sum()),
+            content_type: request.content_type,
+            e_tag: e_tag.clone(),
+            expires: request.expires,
+            metadata: request.metadata,
+            object_lock_legal_hold_status: request.object_lock_legal_hold_status,
+            object_lock_mode: request.object_lock_mode,
+            object_lock_retain_until_date: request.object_lock_retain_until_date,
+            parts_count: Some(completed.len() as i64),
+            sse_customer_algorithm: request.sse_customer_algorithm,
+            sse_customer_key_md5: request.sse_customer_key_md5,
+            ssekms_key_id: request.ssekms_key_id,
+            server_side_encryption: request.server_side_encryption,
+            storage_class: request.storage_class,
+            website_redirect_location: request.website_redirect_location,
+            ..Default::default()
+        };
+        let grants = [
+            parse_grants(&request.grant_read, "READ"),
+            parse_grants(&request.grant_read_acp, "READ_ACP"),
+            parse_grants(&request.grant_write_acp, "WRITE_ACP"),
+            parse_grants(&request.grant_full_control, "FULL_CONTROL"),
+        ]
+        .concat();
+        let object = MemoryObject {
+            head,
+            grants,
+            tags: parse_tags(&request.tagging),
+            body: Vec::new(),
+        };
+        self.put_object(&request.bucket, &request.key, object);
+
+        Ok(CompleteMultipartUploadOutput {
+            bucket: Some(request.bucket),
+            e_tag,
+            key: Some(request.key),
+            ..Default::default()
+        })
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        input: AbortMultipartUploadRequest,
+    ) -> Result<AbortMultipartUploadOutput, RusotoError<AbortMultipartUploadError>> {
+        self.record(StorageCall::AbortMultipartUpload(input.clone()))?;
+        self.uploads
+            .lock()
+            .unwrap()
+            .remove(&input.upload_id)
+            .ok_or_else(not_found)?;
+        Ok(AbortMultipartUploadOutput::default())
+    }
+}
@@ -0,0 +1,210 @@
+use super::errors::RequestError;
+use super::storage::Storage;
+use futures::stream::{StreamExt, TryStreamExt};
+use log::debug;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use rusoto_s3::UploadPartCopyRequest;
+use rusoto_s3::{AbortMultipartUploadRequest, CompleteMultipartUploadRequest};
+use rusoto_s3::{CompletedMultipartUpload, CompletedPart, CopyObjectRequest};
+use rusoto_s3::{CreateMultipartUploadRequest, GetObjectTaggingRequest, Tag};
+
+/// Largest object which can be copied with a single CopyObject request (5 GiB)
+pub const MAX_COPY_OBJECT_SIZE: i64 = 5 * 1024 * 1024 * 1024;
+/// Smallest part size allowed by S3 for all parts but the last (5 MiB)
+pub const MIN_PART_SIZE: i64 = 5 * 1024 * 1024;
+/// Largest number of parts allowed in a multipart upload
+const MAX_PARTS: i64 = 10_000;
+/// Characters left unencoded in the tagging header, i.e. those unreserved in URLs
+const TAGGING_UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Settings for multipart copies
+#[derive(Debug, Clone, Copy)]
+pub struct MultipartOptions {
+    /// Objects larger than this (in bytes) are copied with a multipart upload
+    pub threshold: i64,
+    /// Size of each part in bytes (increased if the object would need more than 10,000 parts)
+    pub part_size: i64,
+    /// Number of parts copied concurrently for each object
+    pub parallelism: usize,
+}
+
+impl Default for MultipartOptions {
+    fn default() -> Self {
+        MultipartOptions {
+            threshold: MAX_COPY_OBJECT_SIZE,
+            part_size: 512 * 1024 * 1024,
+            parallelism: 8,
+        }
+    }
+}
+
+impl MultipartOptions {
+    /// Part size to use for an object of `size` bytes, to keep within the part limit
+    fn part_size_for(&self, size: i64) -> i64 {
+        let min_for_limit = (size + MAX_PARTS - 1) / MAX_PARTS;
+        self.part_size.max(min_for_limit).max(MIN_PART_SIZE)
+    }
+}
+
+/// Copy an object of `size` bytes with UploadPartCopy requests
+///
+/// The object properties, ACL grants and encryption settings are taken from `request` (as built
+/// for a single CopyObject request). There is no tagging directive for multipart uploads, so the
+/// source's tags are fetched and set on the upload, as CopyObject copies them. The upload is
+/// aborted if any part fails.
+pub async fn multipart_copy(
+    client: &dyn Storage,
+    request: CopyObjectRequest,
+    size: i64,
+    options: &MultipartOptions,
+) -> Result<(), anyhow::Error> {
+    let (source_bucket, source_key) = request.copy_source.split_once('/').unwrap_or_default();
+    let tags = client
+        .get_object_tagging(GetObjectTaggingRequest {
+            bucket: String::from(source_bucket),
+            key: String::from(source_key),
+            ..Default::default()
+        })
+        .await
+        .map_err(RequestError::from)?
+        .tag_set;
+    let create_request = CreateMultipartUploadRequest {
+        acl: request.acl.clone(),
+        bucket: request.bucket.clone(),
+        cache_control: request.cache_control.clone(),
+        content_disposition: request.content_disposition.clone(),
+        content_encoding: request.content_encoding.clone(),
+        content_language: request.content_language.clone(),
+        content_type: request.content_type.clone(),
+        expires: request.expires.clone(),
+        grant_full_control: request.grant_full_control.clone(),
+        grant_read: request.grant_read.clone(),
+        grant_read_acp: request.grant_read_acp.clone(),
+        grant_write_acp: request.grant_write_acp.clone(),
+        key: request.key.clone(),
+        metadata: request.metadata.clone(),
+        object_lock_legal_hold_status: request.object_lock_legal_hold_status.clone(),
+        object_lock_mode: request.object_lock_mode.clone(),
+        object_lock_retain_until_date: request.object_lock_retain_until_date.clone(),
+        request_payer: request.request_payer.clone(),
+        sse_customer_algorithm: request.sse_customer_algorithm.clone(),
+        sse_customer_key: request.sse_customer_key.clone(),
+        sse_customer_key_md5: request.sse_customer_key_md5.clone(),
+        ssekms_encryption_context: request.ssekms_encryption_context.clone(),
+        ssekms_key_id: request.ssekms_key_id.clone(),
+        server_side_encryption: request.server_side_encryption.clone(),
+        storage_class: request.storage_class.clone(),
+        tagging: tagging_header(&tags),
+        website_redirect_location: request.website_redirect_location.clone(),
+    };
+    let upload_id = match client
+        .create_multipart_upload(create_request)
+        .await
+        .map_err(RequestError::from)?
+        .upload_id
+    {
+        Some(upload_id) => upload_id,
+        None => anyhow::bail!("No upload ID returned for key: {}", request.key),
+    };
+
+    let part_size = options.part_size_for(size);
+    let part_requests = (0..)
+        .map(|i| i * part_size)
+        .take_while(|start| *start < size)
+        .enumerate()
+        .map(|(i, start)| UploadPartCopyRequest {
+            bucket: request.bucket.clone(),
+            copy_source: request.copy_source.clone(),
+            copy_source_if_match: request.copy_source_if_match.clone(),
+            copy_source_if_modified_since: None,
+            copy_source_if_none_match: None,
+            copy_source_if_unmodified_since: None,
+            copy_source_range: Some(format!(
+                "bytes={}-{}",
+                start,
+                (start + part_size).min(size) - 1
+            )),
+            copy_source_sse_customer_algorithm: request.copy_source_sse_customer_algorithm.clone(),
+            copy_source_sse_customer_key: request.copy_source_sse_customer_key.clone(),
+            copy_source_sse_customer_key_md5: request.copy_source_sse_customer_key_md5.clone(),
+            key: request.key.clone(),
+            part_number: i as i64 + 1,
+            request_payer: request.request_payer.clone(),
+            sse_customer_algorithm: request.sse_customer_algorithm.clone(),
+            sse_customer_key: request.sse_customer_key.clone(),
+            sse_customer_key_md5: request.sse_customer_key_md5.clone(),
+            upload_id: upload_id.clone(),
+        });
+
+    debug!(
+        "Copying {} to {} in parts of {} bytes",
+        request.copy_source, request.key, part_size
+    );
+    let parts: Result<Vec<CompletedPart>, anyhow::Error> = futures::stream::iter(part_requests)
+        .map(|part_request| async move {
+            let part_number = part_request.part_number;
+            let response = client
+                .upload_part_copy(part_request)
+                .await
+                .map_err(RequestError::from)?;
+            Ok(CompletedPart {
+                e_tag: response.copy_part_result.and_then(|x| x.e_tag),
+                part_number: Some(part_number),
+            })
+        })
+        .buffer_unordered(options.parallelism.max(1))
+        .try_collect()
+        .await;
+
+    let result = match parts {
+        Ok(mut parts) => {
+            parts.sort_by_key(|x| x.part_number);
+            client
+                .complete_multipart_upload(CompleteMultipartUploadRequest {
+                    bucket: request.bucket.clone(),
+                    key: request.key.clone(),
+                    multipart_upload: Some(CompletedMultipartUpload { parts: Some(parts) }),
+                    request_payer: request.request_payer.clone(),
+                    upload_id: upload_id.clone(),
+                })
+                .await
+                .map(|_| ())
+                .map_err(|err| RequestError::from(err).into())
+        }
+        Err(err) => Err(err),
+    };
+
+    if let Err(err) = result {
+        debug!("Aborting multipart upload for {}: {:?}", request.key, err);
+        if let Err(abort_err) = client
+            .abort_multipart_upload(AbortMultipartUploadRequest {
+                bucket: request.bucket,
+                key: request.key,
+                request_payer: request.request_payer,
+                upload_id,
+            })
+            .await
+        {
+            debug!("Failed to abort multipart upload: {:?}", abort_err);
+        }
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// URL-encode tags as a query string (e.g. "team=analytics&env=prod") for the tagging header
+fn tagging_header(tags: &[Tag]) -> Option<String> {
+    if tags.is_empty() {
+        return None;
+    }
+    let encode = |x: &str| utf8_percent_encode(x, TAGGING_UNRESERVED).to_string();
+    let pairs: Vec<String> = tags
+        .iter()
+        .map(|x| format!("{}={}", encode(&x.key), encode(&x.value)))
+        .collect();
+    Some(pairs.join("&"))
+}
@@ -0,0 +1,221 @@
+use super::errors::RenameError;
+use super::listing::SourceObject;
+use std::collections::{HashMap, HashSet};
+
+/// A rename which is part of a sequence that must be carried out in order
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct OrderedRename {
+    pub key: SourceObject,
+    pub target: String,
+    /// The target is the source of the previous rename in the sequence, so it will have been
+    /// moved away before this rename is carried out
+    pub target_vacated: bool,
+    /// Set if this is a step in breaking a cycle of renames through a temporary key
+    pub temporary: Option<TemporaryStep>,
+}
+
+/// A rename to or from a temporary key, used to break a cycle of renames
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TemporaryStep {
+    /// Move the key to a temporary key, it will eventually be renamed to `final_target`
+    To { final_target: String },
+    /// Move the key from the temporary key to its final target
+    From,
+}
+
+/// Order a batch of renames so that no key is overwritten before it has been renamed itself
+///
+/// Returns sequences of renames which must each be carried out in order, but can run
+/// concurrently with each other. Chains (a -> b, b -> c) are ordered so that b is renamed before
+/// a, and cycles (a -> b, b -> a) are broken by first renaming one key to a temporary key.
+///
+/// Fails if two keys (including keys which are unchanged) would end up at the same target, unless
+/// `no_overwrite` is set, in which case renames onto an unchanged key are left to be skipped.
+pub(crate) fn order_renames(
+    renames: Vec<(SourceObject, String)>,
+    no_overwrite: bool,
+) -> Result<Vec<Vec<OrderedRename>>, RenameError> {
+    check_collisions(&renames, no_overwrite)?;
+
+    // Unchanged keys are treated like any other existing key, i.e. overwritten unless
+    // `no_overwrite` is set
+    let by_source: HashMap<&str, usize> = renames
+        .iter()
+        .enumerate()
+        .filter(|(_, (key, target))| key.key != *target)
+        .map(|(i, (key, _))| (key.key.as_str(), i))
+        .collect();
+    // For each rename, the rename whose target is its source (so which must run after it)
+    let mut next: Vec<Option<usize>> = vec![None; renames.len()];
+    let mut has_previous = vec![false; renames.len()];
+    for (i, (_, target)) in renames.iter().enumerate() {
+        if let Some(&j) = by_source.get(target.as_str()) {
+            if j != i {
+                next[j] = Some(i);
+                has_previous[i] = true;
+            }
+        }
+    }
+
+    let mut visited = vec![false; renames.len()];
+    let mut sequences: Vec<Vec<usize>> = Vec::new();
+    // Chains start at a rename whose target is not renamed itself
+    for start in (0..renames.len()).filter(|&i| !has_previous[i]) {
+        let mut sequence = Vec::new();
+        let mut current = Some(start);
+        while let Some(i) = current {
+            visited[i] = true;
+            sequence.push(i);
+            current = next[i];
+        }
+        sequences.push(sequence);
+    }
+    // Everything else is part of a cycle
+    let mut cycles: Vec<Vec<usize>> = Vec::new();
+    for start in 0..renames.len() {
+        if visited[start] {
+            continue;
+        }
+        let mut cycle = Vec::new();
+        let mut current = start;
+        loop {
+            visited[current] = true;
+            cycle.push(current);
+            current = next[current].expect("every rename in a cycle is followed by another");
+            if current == start {
+                break;
+            }
+        }
+        cycles.push(cycle);
+    }
+
+    let mut taken: HashSet<String> = renames
+        .iter()
+        .flat_map(|(key, target)| vec![key.key.clone(), target.clone()])
+        .collect();
+    let mut renames: Vec<Option<(SourceObject, String)>> = renames.into_iter().map(Some).collect();
+    let mut ordered: Vec<Vec<OrderedRename>> = sequences
+        .into_iter()
+        .map(|sequence| {
+            sequence
+                .into_iter()
+                .enumerate()
+                .map(|(position, i)| {
+                    let (key, target) = renames[i].take().unwrap();
+                    OrderedRename {
+                        key,
+                        target,
+                        target_vacated: position > 0,
+                        temporary: None,
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    for cycle in cycles {
+        // Move the first key out of the way, rename the rest of the cycle into the space it
+        // leaves, then move it to its target
+        let (first, first_target) = renames[cycle[0]].take().unwrap();
+        let temporary = temporary_key(&first.key, &taken);
+        taken.insert(temporary.clone());
+        let mut sequence = vec![OrderedRename {
+            key: first.clone(),
+            target: temporary.clone(),
+            target_vacated: false,
+            temporary: Some(TemporaryStep::To {
+                final_target: first_target.clone(),
+            }),
+        }];
+        for &i in &cycle[1..] {
+            let (key, target) = renames[i].take().unwrap();
+            sequence.push(OrderedRename {
+                key,
+                target,
+                target_vacated: true,
+                temporary: None,
+            });
+        }
+        sequence.push(OrderedRename {
+            key: SourceObject {
+                key: temporary,
+                // The copy may have a different ETag (e.g. if it was a multipart copy)
+                e_tag: None,
+                ..first
+            },
+            target: first_target,
+            target_vacated: true,
+            temporary: Some(TemporaryStep::From),
+        });
+        ordered.push(sequence);
+    }
+    Ok(ordered)
+}
+
+/// Fail if more than one key would end up at the same target, where an unchanged key holds its
+/// own name (unless `no_overwrite`, since keys renamed onto it are then skipped)
+fn check_collisions(
+    renames: &[(SourceObject, String)],
+    no_overwrite: bool,
+) -> Result<(), RenameError> {
+    let mut by_target: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (key, target) in renames
+        .iter()
+        .filter(|(key, target)| !no_overwrite || key.key != *target)
+    {
+        by_target
+            .entry(target.as_str())
+            .or_default()
+            .push(key.key.as_str());
+    }
+    let mut collisions: Vec<String> = by_target
+        .into_iter()
+        .filter(|(_, sources)| sources.len() > 1)
+        .map(|(target, mut sources)| {
+            sources.sort_unstable();
+            format!("{} <- {}", target, sources.join(", "))
+        })
+        .collect();
+    if collisions.is_empty() {
+        return Ok(());
+    }
+    collisions.sort();
+    Err(RenameError::TargetCollisions {
+        count: collisions.len(),
+        examples: collisions.into_iter().take(10).collect(),
+    })
+}
+
+/// The temporary key used by a sequence which breaks a cycle of renames, if it is one
+pub(crate) fn cycle_temporary_key(sequence: &[OrderedRename]) -> Option<&str> {
+    match sequence.first() {
+        Some(OrderedRename {
+            target,
+            temporary: Some(TemporaryStep::To { .. }),
+            ..
+        }) => Some(target),
+        _ => None,
+    }
+}
+
+/// Use `temporary` instead as the temporary key of a sequence which breaks a cycle of renames
+pub(crate) fn replace_temporary_key(sequence: &mut [OrderedRename], temporary: &str) {
+    for rename in sequence {
+        match rename.temporary {
+            Some(TemporaryStep::To { .. }) => rename.target = String::from(temporary),
+            Some(TemporaryStep::From) => rename.key.key = String::from(temporary),
+            None => {}
+        }
+    }
+}
+
+/// A key next to `key` to hold it while a cycle of renames is broken
+pub(crate) fn temporary_key(key: &str, taken: &HashSet<String>) -> String {
+    let base = format!("{}.s3rename-tmp", key);
+    let mut temporary = base.clone();
+    let mut n = 1;
+    while taken.contains(&temporary) {
+        temporary = format!("{}-{}", base, n);
+        n += 1;
+    }
+    temporary
+}
@@ -0,0 +1,120 @@
+use super::args::CannedACL;
+use super::errors::PlanError;
+use super::listing::list_keys;
+use super::renamer::{PlannedRename, RenameOptions};
+use super::storage::Storage;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Options which affect how the planned renames are carried out
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlanOptions {
+    pub no_preserve_properties: bool,
+    pub no_preserve_acl: bool,
+    pub canned_acl: Option<String>,
+    pub no_overwrite: bool,
+}
+
+/// The full set of renames computed for an expression, to be reviewed and then applied
+///
+/// Written as a JSON file by `s3rename plan`, and carried out exactly by `s3rename apply`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Plan {
+    pub bucket: String,
+    pub key_prefix: Option<String>,
+    /// The expression used to compute the renames (for reference only)
+    pub expr: String,
+    pub options: PlanOptions,
+    /// Renames in order of source key, with the ETag of each source when planned
+    pub renames: Vec<PlannedRename>,
+}
+
+impl Plan {
+    pub(crate) fn new(options: &RenameOptions, mut renames: Vec<PlannedRename>) -> Self {
+        renames.sort_by(|a, b| a.source.cmp(&b.source));
+        Plan {
+            bucket: options.bucket.clone(),
+            key_prefix: options.key_prefix.clone(),
+            expr: options.expr.clone(),
+            options: PlanOptions {
+                no_preserve_properties: options.no_preserve_properties,
+                no_preserve_acl: options.no_preserve_acl,
+                canned_acl: options.canned_acl.map(|x| x.to_string()),
+                no_overwrite: options.no_overwrite,
+            },
+            renames,
+        }
+    }
+
+    pub fn read(path: &Path) -> Result<Self, PlanError> {
+        let file = File::open(path).map_err(|error| PlanError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|error| PlanError::Parse {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), PlanError> {
+        let io_error = |error| PlanError::Io {
+            path: path.to_path_buf(),
+            error,
+        };
+        let mut writer = BufWriter::new(File::create(path).map_err(io_error)?);
+        serde_json::to_writer_pretty(&mut writer, self).map_err(|error| io_error(error.into()))?;
+        writer.write_all(b"\n").map_err(io_error)?;
+        writer.flush().map_err(io_error)
+    }
+
+    /// Options to carry out the plan with, with everything not recorded in the plan set to the
+    /// defaults of `base`
+    pub fn rename_options(&self, base: &RenameOptions) -> Result<RenameOptions, PlanError> {
+        let canned_acl = match &self.options.canned_acl {
+            Some(acl) => Some(CannedACL::from_str(acl).map_err(PlanError::InvalidCannedACL)?),
+            None => None,
+        };
+        Ok(RenameOptions {
+            expr: self.expr.clone(),
+            bucket: self.bucket.clone(),
+            key_prefix: self.key_prefix.clone(),
+            no_preserve_properties: self.options.no_preserve_properties,
+            no_preserve_acl: self.options.no_preserve_acl,
+            canned_acl,
+            no_overwrite: self.options.no_overwrite,
+            ..base.clone()
+        })
+    }
+
+    /// Check that every source key still exists with the ETag it had when planned
+    ///
+    /// Keys are listed rather than requested individually, so this takes one request per 1000
+    /// keys under the prefix.
+    pub async fn check_unchanged(&self, client: &dyn Storage) -> Result<(), anyhow::Error> {
+        let current: HashMap<String, Option<String>> =
+            list_keys(client, &self.bucket, self.key_prefix.clone(), None)
+                .await?
+                .into_iter()
+                .map(|x| (x.key, x.e_tag))
+                .collect();
+        let changed: Vec<String> = self
+            .renames
+            .iter()
+            .filter(|x| current.get(&x.source) != Some(&x.e_tag))
+            .map(|x| x.source.clone())
+            .collect();
+        if !changed.is_empty() {
+            return Err(PlanError::SourcesChanged {
+                count: changed.len(),
+                examples: changed.into_iter().take(10).collect(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
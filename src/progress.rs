@@ -0,0 +1,54 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Counters of the progress of a run, which can be read while it is running (e.g. to display a
+/// progress bar)
+#[derive(Debug, Clone, Default)]
+pub struct Progress(Arc<ProgressCounters>);
+
+#[derive(Debug, Default)]
+struct ProgressCounters {
+    listed: AtomicUsize,
+    bytes_copied: AtomicU64,
+    requests_in_flight: AtomicUsize,
+}
+
+impl Progress {
+    /// Number of keys found so far (listed, or given as exact renames)
+    pub fn listed(&self) -> usize {
+        self.0.listed.load(Ordering::SeqCst)
+    }
+
+    /// Total size of the objects copied so far
+    pub fn bytes_copied(&self) -> u64 {
+        self.0.bytes_copied.load(Ordering::SeqCst)
+    }
+
+    /// Number of S3 requests currently waiting for a response
+    pub fn requests_in_flight(&self) -> usize {
+        self.0.requests_in_flight.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn add_listed(&self, count: usize) {
+        self.0.listed.fetch_add(count, Ordering::SeqCst);
+    }
+
+    pub(crate) fn add_bytes_copied(&self, bytes: u64) {
+        self.0.bytes_copied.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    /// Count a request as in flight until the returned guard is dropped
+    pub(crate) fn start_request(&self) -> RequestInFlight {
+        self.0.requests_in_flight.fetch_add(1, Ordering::SeqCst);
+        RequestInFlight(self.clone())
+    }
+}
+
+/// A request counted by `Progress::requests_in_flight`
+pub(crate) struct RequestInFlight(Progress);
+
+impl Drop for RequestInFlight {
+    fn drop(&mut self) {
+        (self.0).0.requests_in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
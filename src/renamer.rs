@@ -0,0 +1,3195 @@
+use super::args::{App, CannedACL, Command};
+use super::client::{client_for_bucket, ConnectionOptions};
+use super::copy::{copy_object, delete_source, key_exists, verify_copy};
+use super::errors::{GranteeParseError, InventoryError, KeysFromError, RenameError};
+use super::errors::{RequestError, S3Error};
+use super::events::{EventSender, RenameEvent, RenameStage, SkipReason};
+use super::expression::parse_expression;
+use super::filter::{KeyFilters, ObjectFilters};
+use super::inventory::{Inventory, InventoryLocation};
+use super::journal::{Journal, JournalEntry, ResumeState};
+use super::listing::{is_directory_marker, is_empty_marker, list_pages, parent_directories};
+use super::listing::{parse_last_modified, SourceObject};
+use super::manifest::{Manifest, ManifestEntry};
+use super::mapping::read_mapping;
+use super::multipart_copy::MultipartOptions;
+use super::ordering::{cycle_temporary_key, order_renames, replace_temporary_key};
+use super::ordering::{temporary_key, OrderedRename, TemporaryStep};
+use super::plan::Plan;
+use super::progress::Progress;
+use super::report::Report;
+use super::retry::{RetryCounter, RetryPolicy, RetryingStorage};
+use super::storage::Storage;
+use futures::stream::{BoxStream, FuturesUnordered, Stream, StreamExt, TryStreamExt};
+use log::{debug, info, warn};
+use rusoto_s3::{CopyObjectRequest, GetObjectAclRequest, GetObjectTaggingRequest};
+use rusoto_s3::{Grantee, HeadObjectOutput, HeadObjectRequest, S3Client};
+use sedregex::ReplaceCommand;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+/// Options controlling a rename, equivalent to the command line arguments in `args::App`
+#[derive(Debug, Clone)]
+pub struct RenameOptions {
+    /// Perl RegEx Replace Expression (only s/target/replacement/flags form supported)
+    pub expr: String,
+    /// Bucket containing the keys to rename
+    pub bucket: String,
+    /// Only keys under this prefix are renamed
+    pub key_prefix: Option<String>,
+    /// Do not carry out modifications (only emit events)
+    pub dry_run: bool,
+    /// Do not preserve object properties (removes any encryption)
+    pub no_preserve_properties: bool,
+    /// Do not preserve Object ACL settings
+    pub no_preserve_acl: bool,
+    /// Do not allow anonymous capture groups i.e. \1, \2
+    pub no_anonymous_groups: bool,
+    /// Skip keys that would result in an overwrite
+    pub no_overwrite: bool,
+    /// Canned ACL override for all renamed keys
+    pub canned_acl: Option<CannedACL>,
+    /// Settings for copying objects too large for a single CopyObject request
+    pub multipart: MultipartOptions,
+    /// Maximum number of keys being renamed at once
+    pub jobs: usize,
+    /// Maximum number of DeleteObject requests in flight at once
+    pub delete_jobs: usize,
+    /// How requests failing with throttling, server or network errors are retried
+    pub retry: RetryPolicy,
+    /// Rename keys as each page is listed, rather than listing every key first (collisions and
+    /// chains of renames are then only detected within each page)
+    pub stream: bool,
+    /// Levels of common prefixes (split on "/") to discover before listing the keys under each
+    /// prefix concurrently (0 lists every key with a single sequence of requests)
+    pub list_depth: usize,
+    /// Maximum number of ListObjectsV2 requests in flight at once when `list_depth` is set
+    pub list_jobs: usize,
+    /// Only keys included by these filters are renamed (renames of exact keys are not filtered)
+    pub filters: KeyFilters,
+    /// Only objects whose properties match these filters are renamed (renames of exact keys are
+    /// not filtered)
+    pub object_filters: ObjectFilters,
+    /// Rename directory markers (keys ending in "/") like any other key, rather than skipping
+    /// them (renames of exact keys always include them)
+    pub directory_markers: bool,
+    /// Delete the directory markers containing renamed keys which are left with nothing under
+    /// them, once every key is renamed
+    pub remove_empty_markers: bool,
+}
+
+impl RenameOptions {
+    /// Options with everything else set to the command line defaults
+    pub fn new(expr: &str, bucket: &str, key_prefix: Option<&str>) -> Self {
+        RenameOptions {
+            expr: String::from(expr),
+            bucket: String::from(bucket),
+            key_prefix: key_prefix.map(String::from),
+            dry_run: false,
+            no_preserve_properties: false,
+            no_preserve_acl: false,
+            no_anonymous_groups: false,
+            no_overwrite: false,
+            canned_acl: None,
+            multipart: MultipartOptions::default(),
+            jobs: 64,
+            delete_jobs: 64,
+            retry: RetryPolicy::default(),
+            stream: false,
+            list_depth: 0,
+            list_jobs: 16,
+            filters: KeyFilters::default(),
+            object_filters: ObjectFilters::default(),
+            directory_markers: false,
+            remove_empty_markers: false,
+        }
+    }
+}
+
+/// The expression and bucket are left empty if not given (i.e. when a subcommand is used)
+impl From<&App> for RenameOptions {
+    fn from(app: &App) -> Self {
+        let (expr, s3_url) = match &app.command {
+            Some(Command::Plan { expr, s3_url, .. }) => (Some(expr), Some(s3_url)),
+            Some(Command::Map { s3_url, .. }) => (None, Some(s3_url)),
+            _ => (app.expr.as_ref(), app.s3_url.as_ref()),
+        };
+        RenameOptions {
+            expr: expr.cloned().unwrap_or_default(),
+            bucket: s3_url.map(|x| x.bucket.clone()).unwrap_or_default(),
+            key_prefix: s3_url.and_then(|x| x.key_prefix.clone()),
+            dry_run: app.dry_run,
+            no_preserve_properties: app.no_preserve_properties,
+            no_preserve_acl: app.no_preserve_acl,
+            no_anonymous_groups: app.no_anonymous_groups,
+            no_overwrite: app.no_overwrite,
+            canned_acl: app.canned_acl,
+            multipart: MultipartOptions {
+                threshold: app.multipart_threshold,
+                part_size: app.multipart_part_size,
+                parallelism: app.multipart_parallelism,
+            },
+            jobs: app.jobs,
+            delete_jobs: app.delete_jobs,
+            retry: RetryPolicy {
+                max_attempts: app.retry_attempts,
+                base_delay: app.retry_base_delay,
+                max_delay: app.retry_max_delay,
+            },
+            stream: app.stream,
+            list_depth: app.list_depth,
+            list_jobs: app.list_jobs,
+            filters: app.filters.clone(),
+            object_filters: ObjectFilters {
+                modified_before: app.modified_before,
+                modified_after: app.modified_after,
+                min_size: app.min_size,
+                max_size: app.max_size,
+                storage_classes: app.storage_class.iter().map(|x| x.to_uppercase()).collect(),
+                tags: app.tag.clone(),
+                metadata: app.metadata.clone(),
+                content_types: app.content_type.clone(),
+            },
+            directory_markers: app.directory_markers,
+            remove_empty_markers: app.remove_empty_markers,
+        }
+    }
+}
+
+/// A rename of a single key, used to rename exact keys rather than all keys under a prefix
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlannedRename {
+    pub source: String,
+    pub target: String,
+    /// If set, the source is only copied if it still has this ETag
+    pub e_tag: Option<String>,
+}
+
+/// Where the keys to rename with an expression are found
+enum KeySource {
+    /// List all keys under the prefix
+    Listing,
+    /// Read keys from an S3 Inventory report
+    Inventory(Inventory),
+    /// Rename only these keys, in lexicographic order
+    Keys(Vec<SourceObject>),
+}
+
+/// How the keys to rename and their targets are found
+enum Targets {
+    /// List all keys under the prefix and apply the expression to each
+    Expression(ReplaceCommand<'static>),
+    /// Rename exactly these keys
+    Mapping(Vec<PlannedRename>),
+}
+
+/// An object which has been copied to its target key
+struct CopiedObject {
+    /// Size of the source object in bytes, if known
+    size: Option<i64>,
+    storage_class: Option<String>,
+}
+
+/// Renames all keys under a prefix with a sed-style expression
+///
+/// ```no_run
+/// # async fn example() -> Result<(), anyhow::Error> {
+/// use futures::stream::StreamExt;
+/// use s3rename::{ConnectionOptions, RenameEvent, RenameOptions, Renamer};
+///
+/// let options = RenameOptions::new("s/old/new/", "my-bucket", Some("data/"));
+/// let mut events = Renamer::for_bucket(options, &ConnectionOptions::default())
+///     .await?
+///     .run();
+/// while let Some(event) = events.next().await {
+///     if let RenameEvent::Failed { source, error, .. } = event? {
+///         eprintln!("Failed to rename {}: {}", source, error);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Renamer {
+    client: Arc<dyn Storage>,
+    options: RenameOptions,
+    targets: Targets,
+    shutdown: ShutdownHandle,
+    journal: Option<Journal>,
+    resume: Option<ResumeState>,
+    manifest: Option<Manifest>,
+    report: Option<Arc<Report>>,
+    source: KeySource,
+    /// Renames planned during a dry run
+    planned: Mutex<Vec<PlannedRename>>,
+    /// Limits the number of deletes in flight to `options.delete_jobs`
+    delete_permits: Semaphore,
+    retries: RetryCounter,
+    progress: Progress,
+    /// Directories which contained renamed keys, checked for empty markers at the end of the run
+    vacated: Mutex<BTreeSet<String>>,
+}
+
+/// Handle used to stop a running Renamer gracefully
+///
+/// Once shut down, listing stops after the current page and no further keys are copied, but keys
+/// which have already been copied are still verified and deleted. Only keys whose rename had
+/// already started are reported as skipped, keys which were not started are not reported.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle(Arc<AtomicBool>);
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+impl Renamer {
+    /// Create a Renamer which uses the given client for all S3 requests
+    pub fn new(client: Arc<dyn Storage>, options: RenameOptions) -> Result<Self, anyhow::Error> {
+        let replace_command = parse_expression(&options.expr, options.no_anonymous_groups)?;
+        let delete_permits = Semaphore::new(options.delete_jobs.max(1));
+        let retries = RetryCounter::default();
+        let progress = Progress::default();
+        Ok(Renamer {
+            client: Arc::new(RetryingStorage::new(
+                client,
+                options.retry,
+                retries.clone(),
+                progress.clone(),
+            )),
+            options,
+            targets: Targets::Expression(replace_command),
+            shutdown: ShutdownHandle::default(),
+            journal: None,
+            resume: None,
+            manifest: None,
+            report: None,
+            source: KeySource::Listing,
+            planned: Mutex::new(Vec::new()),
+            delete_permits,
+            retries,
+            progress,
+            vacated: Mutex::new(BTreeSet::new()),
+        })
+    }
+
+    /// Create a Renamer which renames exactly the given keys, in order
+    ///
+    /// The expression and key prefix in `options` are not used, and nothing is listed.
+    pub fn from_mapping(
+        client: Arc<dyn Storage>,
+        options: RenameOptions,
+        renames: Vec<PlannedRename>,
+    ) -> Self {
+        let delete_permits = Semaphore::new(options.delete_jobs.max(1));
+        let retries = RetryCounter::default();
+        let progress = Progress::default();
+        Renamer {
+            client: Arc::new(RetryingStorage::new(
+                client,
+                options.retry,
+                retries.clone(),
+                progress.clone(),
+            )),
+            options,
+            targets: Targets::Mapping(renames),
+            shutdown: ShutdownHandle::default(),
+            journal: None,
+            resume: None,
+            manifest: None,
+            report: None,
+            source: KeySource::Listing,
+            planned: Mutex::new(Vec::new()),
+            delete_permits,
+            retries,
+            progress,
+            vacated: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    /// Record every completed rename to `manifest`, so that the run can be undone
+    pub fn with_manifest(mut self, manifest: Manifest) -> Self {
+        self.manifest = Some(manifest);
+        self
+    }
+
+    /// Record the outcome of every key to `report`
+    pub fn with_report(mut self, report: Report) -> Self {
+        self.report = Some(Arc::new(report));
+        self
+    }
+
+    /// Read the keys to rename (and their size and storage class) from an S3 Inventory report,
+    /// rather than listing them
+    pub fn with_inventory(mut self, inventory: Inventory) -> Self {
+        self.source = KeySource::Inventory(inventory);
+        self
+    }
+
+    /// Apply the expression to exactly these keys, rather than listing the keys under the prefix
+    ///
+    /// Keys outside the prefix are ignored. Their size and storage class are fetched with a
+    /// HeadObject request when they are copied.
+    pub fn with_keys(mut self, keys: Vec<String>) -> Self {
+        let mut keys: Vec<SourceObject> = keys
+            .into_iter()
+            .filter(|x| !x.is_empty())
+            .map(|key| SourceObject {
+                key,
+                storage_class: None,
+                size: None,
+                e_tag: None,
+                last_modified: None,
+            })
+            .collect();
+        keys.sort_by(|a, b| a.key.cmp(&b.key));
+        keys.dedup_by(|a, b| a.key == b.key);
+        self.source = KeySource::Keys(keys);
+        self
+    }
+
+    /// Record the progress of the run to `journal`, so that it can be resumed if interrupted
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Resume the run recorded in `journal` (see `Journal::resume`), continuing to record to it
+    ///
+    /// Keys which were deleted are skipped, keys which were copied are verified and deleted
+    /// without being copied again, and listing continues from the last key listed.
+    pub fn resume_from(mut self, journal: Journal, state: ResumeState) -> Self {
+        self.journal = Some(journal);
+        self.resume = Some(state);
+        self
+    }
+
+    /// Create a Renamer with an S3Client for the bucket's region (see `client_for_bucket`)
+    pub async fn for_bucket(
+        options: RenameOptions,
+        connection: &ConnectionOptions,
+    ) -> Result<Self, anyhow::Error> {
+        let client: Arc<S3Client> = Arc::new(client_for_bucket(connection, &options.bucket).await?);
+        Self::new(client, options)
+    }
+
+    /// Create a Renamer from the command line arguments
+    pub async fn from_app(app: &App) -> Result<Self, anyhow::Error> {
+        let connection = ConnectionOptions {
+            aws_region: app.aws_region.clone(),
+            endpoint_url: app.endpoint_url.clone(),
+        };
+        let mut options = RenameOptions::from(app);
+        let renamer = match &app.command {
+            Some(Command::Undo { manifest }) => {
+                let (bucket, renames) = Manifest::undo_renames(manifest)?;
+                options.bucket = bucket;
+                options.key_prefix = None;
+                // The original keys were deleted, so anything there now was written since
+                options.no_overwrite = true;
+                let client = Arc::new(client_for_bucket(&connection, &options.bucket).await?);
+                Self::from_mapping(client, options, renames)
+            }
+            Some(Command::Map { mapping, .. }) => {
+                let renames = read_mapping(mapping)?;
+                options.key_prefix = None;
+                let client = Arc::new(client_for_bucket(&connection, &options.bucket).await?);
+                Self::from_mapping(client, options, renames)
+            }
+            // Nothing is renamed, so there is nothing to record (--report and --manifest are
+            // rejected with plan)
+            Some(Command::Plan { .. }) => {
+                let renamer = Self::for_bucket(options, &connection).await?;
+                return renamer.with_app_key_source(app, &connection).await;
+            }
+            Some(Command::Apply { plan }) => {
+                let plan = Plan::read(plan)?;
+                let options = plan.rename_options(&options)?;
+                let client = Arc::new(client_for_bucket(&connection, &options.bucket).await?);
+                plan.check_unchanged(&*client).await?;
+                Self::from_mapping(client, options, plan.renames)
+            }
+            None => {
+                if let Some(path) = &app.resume {
+                    let (journal, state) = Journal::resume(path, &options)?;
+                    Self::for_bucket(options, &connection)
+                        .await?
+                        .resume_from(journal, state)
+                } else if let Some(path) = &app.journal {
+                    let journal = Journal::create(path, &options)?;
+                    Self::for_bucket(options, &connection)
+                        .await?
+                        .with_journal(journal)
+                } else {
+                    Self::for_bucket(options, &connection).await?
+                }
+                .with_app_key_source(app, &connection)
+                .await?
+            }
+        };
+        let renamer = match &app.manifest {
+            Some(path) => renamer.with_manifest(Manifest::create(path)?),
+            None => renamer,
+        };
+        match &app.report {
+            Some(path) => Ok(renamer.with_report(Report::create(path, app.report_format)?)),
+            None => Ok(renamer),
+        }
+    }
+
+    /// Use the keys given with `--keys-from` or the inventory given with `--inventory`, if any
+    async fn with_app_key_source(
+        self,
+        app: &App,
+        connection: &ConnectionOptions,
+    ) -> Result<Self, anyhow::Error> {
+        if let Some(path) = &app.keys_from {
+            return Ok(self.with_keys(read_keys(path)?));
+        }
+        let inventory = match &app.inventory {
+            // The report is usually in a different bucket, which may be in another region
+            Some(location @ InventoryLocation::S3 { bucket, .. }) => {
+                let client = Arc::new(client_for_bucket(connection, bucket).await?);
+                Inventory::read(client, location).await?
+            }
+            Some(location) => Inventory::read(self.client.clone(), location).await?,
+            None => return Ok(self),
+        };
+        debug!(
+            "Read inventory {} with {} data files",
+            app.inventory.as_ref().unwrap(),
+            inventory.file_count()
+        );
+        Ok(self.with_inventory(inventory))
+    }
+
+    /// Compute the renames without carrying them out (as a dry run), returning them as a plan
+    ///
+    /// Fails if any key could not be planned.
+    pub async fn plan(mut self) -> Result<Plan, anyhow::Error> {
+        self.options.dry_run = true;
+        let options = self.options.clone();
+        let renamer = Arc::new(self);
+        let (events, mut receiver) = EventSender::channel();
+        let events = events.with_report(renamer.report.clone());
+        renamer.clone().execute(events).await?;
+        while let Some(event) = receiver.next().await {
+            if let Ok(RenameEvent::Failed { source, error, .. }) = event {
+                anyhow::bail!("Could not plan rename of {}: {}", source, error);
+            }
+        }
+        let renames = std::mem::take(&mut *renamer.planned.lock().unwrap());
+        Ok(Plan::new(&options, renames))
+    }
+
+    /// Handle which can be used to stop the run started by `run`
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Path of the journal recording the run, if there is one
+    pub fn journal_path(&self) -> Option<PathBuf> {
+        self.journal.as_ref().map(|x| x.path().to_path_buf())
+    }
+
+    /// Counter of the S3 requests retried by this Renamer, which can be read after `run`
+    pub fn retry_counter(&self) -> RetryCounter {
+        self.retries.clone()
+    }
+
+    /// Counters of the progress of the run started by `run`, which can be read while it runs
+    pub fn progress(&self) -> Progress {
+        self.progress.clone()
+    }
+
+    /// Start renaming keys, returning a stream of events for each key
+    ///
+    /// The stream yields an `Err` and ends if the run cannot continue (e.g. listing fails).
+    /// Per-key failures are reported as `RenameEvent::Failed`. The rename continues in the
+    /// background if the stream is dropped.
+    pub fn run(self) -> impl Stream<Item = Result<RenameEvent, anyhow::Error>> {
+        let (events, receiver) = EventSender::channel();
+        let events = events.with_report(self.report.clone());
+        let renamer = Arc::new(self);
+        tokio::spawn(async move {
+            let result = renamer.clone().execute(events.clone()).await;
+            if renamer.options.remove_empty_markers {
+                renamer.remove_empty_markers().await;
+            }
+            if let Err(error) = result {
+                events.send_error(error);
+            }
+        });
+        receiver
+    }
+
+    async fn execute(self: Arc<Self>, events: EventSender) -> Result<(), anyhow::Error> {
+        let replace_command = match &self.targets {
+            Targets::Expression(replace_command) => replace_command,
+            Targets::Mapping(renames) => {
+                let renames = renames
+                    .iter()
+                    .map(|x| {
+                        let key = SourceObject {
+                            key: x.source.clone(),
+                            storage_class: None,
+                            size: None,
+                            e_tag: x.e_tag.clone(),
+                            last_modified: None,
+                        };
+                        (key, x.target.clone())
+                    })
+                    .collect::<Vec<_>>();
+                self.progress.add_listed(renames.len());
+                return self.rename_all(renames, events).await;
+            }
+        };
+
+        // Without --stream every key is listed before any is renamed, so that the whole batch
+        // can be checked for collisions and ordered
+        let mut batch = match &self.resume {
+            Some(resume) => resume.remaining().to_vec(),
+            None => Vec::new(),
+        };
+        self.progress.add_listed(batch.len());
+        let mut in_flight = FuturesUnordered::new();
+        let result = async {
+            if !self.resume.as_ref().is_some_and(|x| x.listing_complete()) {
+                let start_after = self
+                    .resume
+                    .as_ref()
+                    .and_then(|x| x.last_listed())
+                    .map(String::from);
+                let mut pages = self.key_pages(start_after)?;
+                while let Some(page) = pages.try_next().await? {
+                    batch.extend(self.listed(page, &events)?);
+                    if self.options.stream {
+                        let renames = with_targets(replace_command, std::mem::take(&mut batch));
+                        self.spawn_renames(renames, &mut in_flight, &events).await?;
+                    }
+                    if self.shutdown.is_shutdown() {
+                        debug!("Shutting down, no further keys will be listed");
+                        return Ok(());
+                    }
+                }
+                self.record(JournalEntry::ListingComplete)?;
+            }
+            let renames = with_targets(replace_command, batch);
+            self.spawn_renames(renames, &mut in_flight, &events).await
+        }
+        .await;
+        // Keys already being renamed are finished even if listing fails part way through
+        let finished = finish_renames(&mut in_flight).await;
+        result.and(finished)
+    }
+
+    /// Pages of keys to rename in lexicographic order
+    fn key_pages(
+        &self,
+        start_after: Option<String>,
+    ) -> Result<BoxStream<'_, Result<Vec<SourceObject>, anyhow::Error>>, anyhow::Error> {
+        let prefix = self.options.key_prefix.clone().unwrap_or_default();
+        let pages: BoxStream<'_, Result<Vec<SourceObject>, anyhow::Error>> = match &self.source {
+            KeySource::Listing => {
+                return Ok(list_pages(
+                    &*self.client,
+                    &self.options.bucket,
+                    self.options.key_prefix.clone(),
+                    start_after,
+                    self.options.list_depth,
+                    self.options.list_jobs,
+                ))
+            }
+            KeySource::Inventory(inventory) => {
+                if inventory.source_bucket() != self.options.bucket {
+                    return Err(InventoryError::BucketMismatch {
+                        inventory_bucket: String::from(inventory.source_bucket()),
+                        bucket: self.options.bucket.clone(),
+                    }
+                    .into());
+                }
+                inventory
+                    .pages(Some(prefix.clone()), start_after.clone())
+                    .err_into()
+                    .boxed()
+            }
+            KeySource::Keys(keys) => {
+                let start = match &start_after {
+                    Some(after) => keys.partition_point(|x| x.key <= *after),
+                    None => 0,
+                };
+                let key_prefix = prefix.clone();
+                let keys = keys[start..]
+                    .iter()
+                    .filter(move |x| x.key.starts_with(&key_prefix));
+                // Pages of the same size as listing, so that --stream works in the same way
+                futures::stream::iter(keys)
+                    .chunks(1000)
+                    .map(|x| Ok(x.into_iter().cloned().collect()))
+                    .boxed()
+            }
+        };
+        // As with listing, finding no keys at all is an error
+        let empty = start_after.is_none().then(|| S3Error::EmptyBucket {
+            bucket: self.options.bucket.clone(),
+            prefix,
+        });
+        Ok(
+            futures::stream::unfold((pages, empty), |(mut pages, empty)| async move {
+                match pages.next().await {
+                    Some(page) => Some((page, (pages, None))),
+                    None => empty.map(|error| (Err(error.into()), (pages, None))),
+                }
+            })
+            .fuse()
+            .boxed(),
+        )
+    }
+
+    /// Rename each key to its target concurrently, until shut down
+    async fn rename_all(
+        self: Arc<Self>,
+        renames: Vec<(SourceObject, String)>,
+        events: EventSender,
+    ) -> Result<(), anyhow::Error> {
+        let mut in_flight = FuturesUnordered::new();
+        let result = self.spawn_renames(renames, &mut in_flight, &events).await;
+        let finished = finish_renames(&mut in_flight).await;
+        result.and(finished)
+    }
+
+    /// Order a batch of renames and start renaming them, waiting whenever `options.jobs`
+    /// sequences of renames are already in flight
+    ///
+    /// Nothing is started if the batch contains colliding targets.
+    async fn spawn_renames(
+        self: &Arc<Self>,
+        renames: Vec<(SourceObject, String)>,
+        in_flight: &mut FuturesUnordered<JoinHandle<()>>,
+        events: &EventSender,
+    ) -> Result<(), anyhow::Error> {
+        let mut sequences = order_renames(renames, self.options.no_overwrite)?;
+        self.free_temporary_keys(&mut sequences).await?;
+        for sequence in sequences {
+            if in_flight.len() >= self.options.jobs.max(1) {
+                if let Some(Err(error)) = in_flight.next().await {
+                    return Err(RenameError::TaskFailed { error }.into());
+                }
+            }
+            if self.shutdown.is_shutdown() {
+                debug!("Shutting down, no further keys will be renamed");
+                break;
+            }
+            in_flight.push(tokio::spawn(
+                self.clone().rename_sequence(sequence, events.clone()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Move the temporary keys used to break cycles of renames to keys which do not exist in the
+    /// bucket, since keys outside the batch (e.g. excluded by filters, or listed in a later page)
+    /// are not known when the renames are ordered
+    async fn free_temporary_keys(
+        &self,
+        sequences: &mut [Vec<OrderedRename>],
+    ) -> Result<(), anyhow::Error> {
+        let mut taken: HashSet<String> = sequences
+            .iter()
+            .flatten()
+            .flat_map(|x| vec![x.key.key.clone(), x.target.clone()])
+            .collect();
+        for sequence in sequences.iter_mut() {
+            let (source, mut temporary) = match cycle_temporary_key(sequence) {
+                Some(temporary) => (sequence[0].key.key.clone(), String::from(temporary)),
+                None => continue,
+            };
+            let original = temporary.clone();
+            while key_exists(&*self.client, &self.options.bucket, &temporary).await? {
+                taken.insert(temporary.clone());
+                temporary = temporary_key(&source, &taken);
+            }
+            if temporary != original {
+                replace_temporary_key(sequence, &temporary);
+                taken.insert(temporary);
+            }
+        }
+        Ok(())
+    }
+
+    /// Rename keys one after another, stopping if any is not renamed (since the next key would
+    /// overwrite it)
+    async fn rename_sequence(self: Arc<Self>, sequence: Vec<OrderedRename>, events: EventSender) {
+        let mut sequence = sequence.into_iter();
+        while let Some(rename) = sequence.next() {
+            self.report_started(&rename.key);
+            let renamed = self.clone().handle_key(rename, events.clone()).await;
+            if !renamed && !self.options.dry_run {
+                for rename in sequence {
+                    self.report_started(&rename.key);
+                    debug!(
+                        "Skipping {} since an earlier rename in its chain did not complete",
+                        rename.key.key
+                    );
+                    events.send(RenameEvent::Skipped {
+                        source: rename.key.key,
+                        target: rename.target,
+                        reason: SkipReason::ChainBroken,
+                    });
+                }
+                return;
+            }
+        }
+    }
+
+    /// Record a page of listed keys to the journal, dropping keys excluded by the filters and
+    /// skipping keys created by a resumed run
+    fn listed(
+        &self,
+        mut page: Vec<SourceObject>,
+        events: &EventSender,
+    ) -> Result<Vec<SourceObject>, anyhow::Error> {
+        if !self.options.directory_markers {
+            page.retain(|x| !is_directory_marker(&x.key));
+        }
+        if !self.options.filters.is_empty() {
+            page.retain(|x| self.options.filters.includes(&x.key));
+        }
+        // Keys whose properties are not all known are checked when they are copied
+        page.retain(|x| self.options.object_filters.includes(x) != Some(false));
+        self.progress.add_listed(page.len());
+        let mut keys = Vec::with_capacity(page.len());
+        for key in page {
+            self.record(JournalEntry::Listed {
+                source: key.key.clone(),
+                storage_class: key.storage_class.clone(),
+                size: key.size,
+            })?;
+            match &self.resume {
+                Some(resume) if resume.is_target(&key.key) => {
+                    debug!(
+                        "Skipping {} since it was created by the resumed run",
+                        key.key
+                    );
+                    events.send(RenameEvent::Skipped {
+                        source: key.key.clone(),
+                        target: key.key,
+                        reason: SkipReason::AlreadyRenamed,
+                    });
+                }
+                _ => keys.push(key),
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Delete the directory markers containing renamed keys which now have nothing under them,
+    /// deepest first so that their parents can be emptied in turn
+    ///
+    /// Failures are logged rather than failing the run, since every key has been renamed.
+    async fn remove_empty_markers(&self) {
+        let vacated = std::mem::take(&mut *self.vacated.lock().unwrap());
+        let bucket = &self.options.bucket;
+        // A directory sorts before every key under it
+        for marker in vacated.iter().rev() {
+            let removed = async {
+                if !is_empty_marker(&*self.client, bucket, marker).await? {
+                    return Ok(false);
+                }
+                delete_source(&*self.client, bucket, marker).await?;
+                Ok::<_, anyhow::Error>(true)
+            }
+            .await;
+            match removed {
+                Ok(true) => {
+                    info!(key = marker.as_str(); "Removed empty directory marker {}", marker)
+                }
+                Ok(false) => {}
+                Err(error) => warn!(
+                    key = marker.as_str(), error:% = error;
+                    "Could not remove empty directory marker {}: {}", marker, error
+                ),
+            }
+        }
+    }
+
+    /// Note that renaming a key has started in the report, if there is one
+    fn report_started(&self, key: &SourceObject) {
+        if let Some(report) = &self.report {
+            report.start(key);
+        }
+    }
+
+    /// Write an entry to the journal, if there is one
+    fn record(&self, entry: JournalEntry) -> Result<(), anyhow::Error> {
+        if let Some(journal) = &self.journal {
+            journal.record(&entry)?;
+        }
+        Ok(())
+    }
+
+    /// Rename a single key, moving it through each stage of the pipeline in turn
+    ///
+    /// The source is only deleted once the copy has been verified (and recorded in the journal),
+    /// and every failure is reported as an event with the last stage the key reached. Returns
+    /// whether the source key was deleted.
+    async fn handle_key(self: Arc<Self>, rename: OrderedRename, events: EventSender) -> bool {
+        let OrderedRename {
+            key,
+            target,
+            target_vacated,
+            temporary,
+        } = rename;
+        let source = key.key.clone();
+        // The temporary key was only created by this run, so is not filtered again
+        let filter = temporary != Some(TemporaryStep::From);
+        // Renames through a temporary key are planned as a single rename to the final target
+        let planned = match temporary {
+            None => Some(target.clone()),
+            Some(TemporaryStep::To { final_target }) => Some(final_target),
+            Some(TemporaryStep::From) => None,
+        }
+        .map(|target| PlannedRename {
+            source: source.clone(),
+            target,
+            e_tag: key.e_tag.clone(),
+        });
+        let incomplete = self
+            .resume
+            .as_ref()
+            .and_then(|resume| resume.incomplete(&source))
+            .cloned();
+        let target = match &incomplete {
+            Some((target, _)) => target.clone(),
+            None => target,
+        };
+        let failed = |stage: RenameStage, error: anyhow::Error| {
+            events.send(RenameEvent::Failed {
+                source: source.clone(),
+                target: Some(target.clone()),
+                stage,
+                error: format!("{:#}", error),
+                request_id: error
+                    .chain()
+                    .find_map(|x| x.downcast_ref::<RequestError>())
+                    .and_then(|x| x.request_id().map(String::from)),
+            });
+            false
+        };
+
+        let size = if let Some((_, stage)) = incomplete {
+            // Copied by the resumed run, so only the verification and deletion remain
+            debug!(
+                "Resuming rename of {} to {} (key was {})",
+                source, target, stage
+            );
+            events.send(RenameEvent::Planned {
+                source: source.clone(),
+                target: target.clone(),
+            });
+            key.size
+        } else {
+            let size = match self
+                .copy_key(
+                    key,
+                    target.clone(),
+                    target_vacated,
+                    filter,
+                    planned,
+                    &events,
+                )
+                .await
+            {
+                Ok(Some(copied)) => {
+                    // The size and storage class may only have been found when copying
+                    self.report_started(&SourceObject {
+                        key: source.clone(),
+                        storage_class: copied.storage_class,
+                        size: copied.size,
+                        e_tag: None,
+                        last_modified: None,
+                    });
+                    self.progress
+                        .add_bytes_copied(copied.size.unwrap_or_default().max(0) as u64);
+                    copied.size
+                }
+                Ok(None) => return false,
+                Err(error) => return failed(RenameStage::Planned, error),
+            };
+            if let Err(error) = self.record(JournalEntry::Copied {
+                source: source.clone(),
+                target: target.clone(),
+            }) {
+                return failed(RenameStage::Copied, error);
+            }
+            size
+        };
+        events.send(RenameEvent::Copied {
+            source: source.clone(),
+            target: target.clone(),
+        });
+
+        let copy = match verify_copy(&*self.client, &self.options.bucket, &target, size).await {
+            Ok(copy) => copy,
+            Err(error) => return failed(RenameStage::Copied, error),
+        };
+        if let Err(error) = self.record(JournalEntry::Verified {
+            source: source.clone(),
+            target: target.clone(),
+        }) {
+            return failed(RenameStage::Copied, error);
+        }
+        events.send(RenameEvent::Verified {
+            source: source.clone(),
+            target: target.clone(),
+        });
+
+        let permit = self.delete_permits.acquire().await;
+        let deleted = delete_source(&*self.client, &self.options.bucket, &source).await;
+        drop(permit);
+        if let Err(error) = deleted {
+            return failed(RenameStage::Verified, error);
+        }
+        if self.options.remove_empty_markers {
+            let mut vacated = self.vacated.lock().unwrap();
+            vacated.extend(parent_directories(&source).map(String::from));
+        }
+        if let Err(error) = self.record(JournalEntry::Deleted {
+            source: source.clone(),
+            target: target.clone(),
+        }) {
+            failed(RenameStage::Deleted, error);
+            return true;
+        }
+        if let Some(manifest) = &self.manifest {
+            let entry = ManifestEntry {
+                bucket: self.options.bucket.clone(),
+                source: source.clone(),
+                target: target.clone(),
+                e_tag: copy.e_tag,
+                version_id: copy.version_id,
+            };
+            if let Err(error) = manifest.record(&entry) {
+                failed(RenameStage::Deleted, error.into());
+                return true;
+            }
+        }
+        events.send(RenameEvent::Deleted { source, target });
+        true
+    }
+
+    /// Check `key` against the object filters, fetching what is needed to decide: the HEAD of
+    /// keys which were not listed (e.g. given with --keys-from) or of any key for metadata and
+    /// content type filters, and the tags of any key for tag filters
+    ///
+    /// Returns the key with any properties fetched, whether it is included, and the HEAD response
+    /// if one was made.
+    async fn filter_object(
+        &self,
+        key: SourceObject,
+    ) -> Result<(SourceObject, bool, Option<HeadObjectOutput>), anyhow::Error> {
+        let filters = &self.options.object_filters;
+        let mut key = key;
+        let mut included = filters.includes(&key);
+        let mut source_head = None;
+        if included.is_none() || (included == Some(true) && filters.needs_head()) {
+            let head_request = HeadObjectRequest {
+                bucket: self.options.bucket.clone(),
+                key: key.key.clone(),
+                ..Default::default()
+            };
+            let head_result = self
+                .client
+                .head_object(head_request)
+                .await
+                .map_err(RequestError::from)?;
+            key = SourceObject {
+                size: key.size.or(head_result.content_length),
+                // HEAD omits the storage class of STANDARD objects
+                storage_class: key.storage_class.or_else(|| {
+                    Some(
+                        head_result
+                            .storage_class
+                            .clone()
+                            .unwrap_or_else(|| String::from("STANDARD")),
+                    )
+                }),
+                last_modified: key.last_modified.or_else(|| {
+                    head_result
+                        .last_modified
+                        .as_deref()
+                        .and_then(parse_last_modified)
+                }),
+                ..key
+            };
+            included = filters
+                .includes(&key)
+                .map(|x| x && filters.includes_head(&head_result));
+            source_head = Some(head_result);
+        }
+        if included == Some(true) && filters.needs_tags() {
+            let tagging_request = GetObjectTaggingRequest {
+                bucket: self.options.bucket.clone(),
+                key: key.key.clone(),
+                ..Default::default()
+            };
+            let tagging = self
+                .client
+                .get_object_tagging(tagging_request)
+                .await
+                .map_err(RequestError::from)?;
+            included = Some(filters.includes_tags(&tagging.tag_set));
+        }
+        Ok((key, included == Some(true), source_head))
+    }
+
+    /// Copy `key.key` to `newkey`
+    ///
+    /// Returns `None` if the key is skipped (or this is a dry run, in which case `planned` is
+    /// recorded). If `target_vacated`, the target is renamed away beforehand so is not checked for
+    /// overwriting. The key is only checked against the object filters if `filter`.
+    async fn copy_key(
+        &self,
+        key: SourceObject,
+        newkey: String,
+        target_vacated: bool,
+        filter: bool,
+        planned: Option<PlannedRename>,
+        events: &EventSender,
+    ) -> Result<Option<CopiedObject>, anyhow::Error> {
+        let bucket = &self.options.bucket;
+        let canned_acl = self.options.canned_acl;
+        if newkey == key.key {
+            debug!("Skipping {:?} since key did not change", key);
+            events.send(RenameEvent::Skipped {
+                source: key.key,
+                target: newkey,
+                reason: SkipReason::Unchanged,
+            });
+            return Ok(None);
+        }
+        // Objects are checked against the filters which the listing could not decide before
+        // anything is planned, and any HEAD response is reused to copy their properties
+        let (key, source_head) = match self.targets {
+            Targets::Expression(_) if filter => {
+                let (key, included, source_head) = self.filter_object(key).await?;
+                if !included {
+                    debug!("Skipping {} since it is excluded by the filters", key.key);
+                    events.send(RenameEvent::Skipped {
+                        source: key.key,
+                        target: newkey,
+                        reason: SkipReason::Filtered,
+                    });
+                    return Ok(None);
+                }
+                (key, source_head)
+            }
+            _ => (key, None),
+        };
+        if self.options.no_overwrite && !target_vacated {
+            let head_request = HeadObjectRequest {
+                bucket: bucket.clone(),
+                if_match: None,
+                if_modified_since: None,
+                if_none_match: None,
+                if_unmodified_since: None,
+                key: newkey.clone(),
+                part_number: None,
+                range: None,
+                request_payer: None,
+                sse_customer_algorithm: None, // Cannot be empty if using sse-c
+                sse_customer_key: None,
+                sse_customer_key_md5: None,
+                version_id: None,
+            };
+            let head_result = self.client.head_object(head_request).await;
+            if let Ok(head_result) = head_result {
+                if head_result.metadata.is_some() {
+                    debug!("Skipping {} since this would result in overwriting", newkey);
+                    events.send(RenameEvent::Skipped {
+                        source: key.key,
+                        target: newkey,
+                        reason: SkipReason::WouldOverwrite,
+                    });
+                    return Ok(None);
+                }
+            }
+        }
+        events.send(RenameEvent::Planned {
+            source: key.key.clone(),
+            target: newkey.clone(),
+        });
+        if self.options.dry_run {
+            if let Some(mut planned) = planned {
+                // Keys which were not listed (e.g. given with --keys-from) have their ETag looked
+                // up, so that applying the plan can check they are unchanged
+                if planned.e_tag.is_none() {
+                    planned.e_tag = match &source_head {
+                        Some(head_result) => head_result.e_tag.clone(),
+                        None => {
+                            let head_request = HeadObjectRequest {
+                                bucket: bucket.clone(),
+                                key: key.key.clone(),
+                                ..Default::default()
+                            };
+                            self.client
+                                .head_object(head_request)
+                                .await
+                                .map_err(RequestError::from)?
+                                .e_tag
+                        }
+                    };
+                }
+                self.planned.lock().unwrap().push(planned);
+            }
+            events.send(RenameEvent::Skipped {
+                source: key.key,
+                target: newkey,
+                reason: SkipReason::DryRun,
+            });
+            return Ok(None);
+        }
+
+        // Exact keys are only renamed if unchanged since they were planned
+        let if_match = match self.targets {
+            Targets::Expression(_) => None,
+            Targets::Mapping(_) => key.e_tag.clone(),
+        };
+
+        let mut grant_read_vec: Vec<String> = Vec::new();
+        let mut grant_read_acp_vec: Vec<String> = Vec::new();
+        let mut grant_write_acp_vec: Vec<String> = Vec::new();
+        let mut grant_full_control_vec: Vec<String> = Vec::new();
+
+        if !self.options.no_preserve_acl && canned_acl.is_none() {
+            let acl_request = GetObjectAclRequest {
+                bucket: bucket.clone(),
+                key: key.key.clone(),
+                request_payer: None,
+                version_id: None,
+            };
+            let acl_response = self
+                .client
+                .get_object_acl(acl_request)
+                .await
+                .map_err(RequestError::from)?;
+            debug!("{:?}", acl_response);
+
+            for grant in acl_response.grants.unwrap() {
+                match grant.permission.as_deref() {
+                    Some("READ") => {
+                        let grantee = grant.grantee.unwrap();
+                        grant_read_vec.push(generate_permission_grant(grantee)?);
+                    }
+                    Some("WRITE") => {
+                        //TODO: No WRITE grant on CopyObjectRequest - is this controlled by bucket ACL?
+                        debug!(
+                            "Warning: WRITE access ignored for grantee: {:?} on key: {}",
+                            grant.grantee.unwrap(),
+                            &key.key
+                        );
+                    }
+                    Some("READ_ACP") => {
+                        let grantee = grant.grantee.unwrap();
+                        grant_read_acp_vec.push(generate_permission_grant(grantee)?);
+                    }
+                    Some("WRITE_ACP") => {
+                        let grantee = grant.grantee.unwrap();
+                        grant_write_acp_vec.push(generate_permission_grant(grantee)?);
+                    }
+                    Some("FULL_CONTROL") => {
+                        let grantee = grant.grantee.unwrap();
+                        grant_full_control_vec.push(generate_permission_grant(grantee)?);
+                    }
+                    Some(other) => {
+                        return Err(GranteeParseError::InvalidPermission {
+                            permission: String::from(other),
+                            grantee: Box::new(grant.grantee.unwrap()),
+                        }
+                        .into())
+                    }
+                    None => {
+                        return Err(GranteeParseError::MissingPermission {
+                            grantee: Box::new(grant.grantee.unwrap()),
+                        }
+                        .into())
+                    }
+                }
+            }
+        }
+        // Keys which were not listed (e.g. given with --keys-from) only have their size and
+        // storage class looked up when they are copied
+        let key = match (self.options.no_preserve_properties, key.size) {
+            (true, None) => {
+                let head_request = HeadObjectRequest {
+                    bucket: bucket.clone(),
+                    key: key.key.clone(),
+                    ..Default::default()
+                };
+                let head_result = self
+                    .client
+                    .head_object(head_request)
+                    .await
+                    .map_err(RequestError::from)?;
+                SourceObject {
+                    size: head_result.content_length,
+                    storage_class: key.storage_class.or(head_result.storage_class),
+                    ..key
+                }
+            }
+            _ => key,
+        };
+        let (copy_request, size) = match self.options.no_preserve_properties {
+            false => {
+                let head_result = match source_head {
+                    Some(head_result) => head_result,
+                    None => {
+                        let head_request = HeadObjectRequest {
+                            bucket: bucket.clone(),
+                            key: key.key.clone(),
+                            ..Default::default()
+                        };
+                        self.client
+                            .head_object(head_request)
+                            .await
+                            .map_err(RequestError::from)?
+                    }
+                };
+                let size = head_result.content_length.or(key.size);
+                let copy_request = CopyObjectRequest {
+                    acl: canned_acl.map(|x| x.to_string()),
+                    bucket: bucket.clone(),
+                    cache_control: head_result.cache_control,
+                    content_disposition: head_result.content_disposition,
+                    content_encoding: head_result.content_encoding,
+                    content_language: head_result.content_language,
+                    content_type: head_result.content_type,
+                    copy_source: format!("{}/{}", bucket, key.key),
+                    copy_source_if_match: if_match.clone(),
+                    copy_source_if_modified_since: None,
+                    copy_source_if_none_match: None,
+                    copy_source_if_unmodified_since: None,
+                    copy_source_sse_customer_algorithm: head_result.sse_customer_algorithm.clone(),
+                    copy_source_sse_customer_key: None, //TODO
+                    copy_source_sse_customer_key_md5: head_result.sse_customer_key_md5.clone(),
+                    expires: head_result.expires,
+                    grant_full_control: if !grant_full_control_vec.is_empty() {
+                        Some(grant_full_control_vec.join(", "))
+                    } else {
+                        None
+                    },
+                    grant_read: if !grant_read_vec.is_empty() {
+                        Some(grant_read_vec.join(", "))
+                    } else {
+                        None
+                    },
+                    grant_read_acp: if !grant_read_acp_vec.is_empty() {
+                        Some(grant_read_acp_vec.join(", "))
+                    } else {
+                        None
+                    },
+                    grant_write_acp: if !grant_write_acp_vec.is_empty() {
+                        Some(grant_write_acp_vec.join(", "))
+                    } else {
+                        None
+                    },
+                    key: newkey.clone(),
+                    metadata: head_result.metadata,
+                    metadata_directive: Some(String::from("REPLACE")), // Set to REPLACE due to
+                    // multi-part copies: https://docs.aws.amazon.com/cli/latest/reference/s3/cp.html
+                    object_lock_legal_hold_status: head_result.object_lock_legal_hold_status,
+                    object_lock_mode: head_result.object_lock_mode,
+                    object_lock_retain_until_date: head_result.object_lock_retain_until_date,
+                    request_payer: head_result.request_charged, // TODO: Test me
+                    sse_customer_algorithm: head_result.sse_customer_algorithm.clone(),
+                    sse_customer_key: None, // TODO
+                    sse_customer_key_md5: head_result.sse_customer_key_md5.clone(),
+                    ssekms_encryption_context: None, // TODO
+                    ssekms_key_id: head_result.ssekms_key_id,
+                    server_side_encryption: head_result.server_side_encryption,
+                    storage_class: key.storage_class.clone().or(head_result.storage_class),
+                    tagging: None, // tagging_directive should cover this anyway
+                    tagging_directive: Some(String::from("COPY")),
+                    website_redirect_location: head_result.website_redirect_location,
+                };
+                (copy_request, size)
+            }
+            true => {
+                // CreateMultipartUpload has no metadata directive, so the headers and metadata of
+                // multipart copies are always set from the source
+                let multipart = key
+                    .size
+                    .is_some_and(|x| x > self.options.multipart.threshold);
+                let head_result = match source_head {
+                    Some(head_result) if multipart => head_result,
+                    None if multipart => {
+                        let head_request = HeadObjectRequest {
+                            bucket: bucket.clone(),
+                            key: key.key.clone(),
+                            ..Default::default()
+                        };
+                        self.client
+                            .head_object(head_request)
+                            .await
+                            .map_err(RequestError::from)?
+                    }
+                    _ => HeadObjectOutput::default(),
+                };
+                let copy_request = CopyObjectRequest {
+                    acl: canned_acl.map(|x| x.to_string()),
+                    bucket: bucket.clone(),
+                    cache_control: head_result.cache_control,
+                    content_disposition: head_result.content_disposition,
+                    content_encoding: head_result.content_encoding,
+                    content_language: head_result.content_language,
+                    content_type: head_result.content_type,
+                    copy_source: format!("{}/{}", bucket, key.key),
+                    copy_source_if_match: if_match.clone(),
+                    copy_source_if_modified_since: None,
+                    copy_source_if_none_match: None,
+                    copy_source_if_unmodified_since: None,
+                    copy_source_sse_customer_algorithm: None,
+                    copy_source_sse_customer_key: None,
+                    copy_source_sse_customer_key_md5: None,
+                    expires: head_result.expires,
+                    grant_full_control: if !grant_full_control_vec.is_empty() {
+                        Some(grant_full_control_vec.join(", "))
+                    } else {
+                        None
+                    },
+                    grant_read: if !grant_read_vec.is_empty() {
+                        Some(grant_read_vec.join(", "))
+                    } else {
+                        None
+                    },
+                    grant_read_acp: if !grant_read_acp_vec.is_empty() {
+                        Some(grant_read_acp_vec.join(", "))
+                    } else {
+                        None
+                    },
+                    grant_write_acp: if !grant_write_acp_vec.is_empty() {
+                        Some(grant_write_acp_vec.join(", "))
+                    } else {
+                        None
+                    },
+                    key: newkey.clone(),
+                    metadata: head_result.metadata,
+                    metadata_directive: Some(String::from("COPY")),
+                    object_lock_legal_hold_status: None,
+                    object_lock_mode: None,
+                    object_lock_retain_until_date: None,
+                    request_payer: None,
+                    sse_customer_algorithm: None,
+                    sse_customer_key: None,
+                    sse_customer_key_md5: None,
+                    ssekms_encryption_context: None,
+                    ssekms_key_id: None,
+                    server_side_encryption: None,
+                    storage_class: key.storage_class.clone(),
+                    tagging: None,
+                    tagging_directive: Some(String::from("COPY")),
+                    website_redirect_location: head_result.website_redirect_location,
+                };
+                (copy_request, key.size)
+            }
+        };
+
+        // Last chance to stop before modifying anything
+        if self.shutdown.is_shutdown() {
+            events.send(RenameEvent::Skipped {
+                source: key.key,
+                target: newkey,
+                reason: SkipReason::Interrupted,
+            });
+            return Ok(None);
+        }
+        copy_object(&*self.client, copy_request, size, &self.options.multipart).await?;
+        Ok(Some(CopiedObject {
+            size,
+            storage_class: key.storage_class,
+        }))
+    }
+}
+
+/// Wait for every sequence of renames in flight to finish
+///
+/// Every key of a sequence is reported with an event unless its task panicked, so a panic fails
+/// the run rather than leaving keys unaccounted for.
+async fn finish_renames(
+    in_flight: &mut FuturesUnordered<JoinHandle<()>>,
+) -> Result<(), anyhow::Error> {
+    let mut result = Ok(());
+    while let Some(handled) = in_flight.next().await {
+        if let Err(error) = handled {
+            result = Err(RenameError::TaskFailed { error }.into());
+        }
+    }
+    result
+}
+
+/// Read keys, one per line, from a file or from stdin if `path` is "-"
+fn read_keys(path: &Path) -> Result<Vec<String>, KeysFromError> {
+    let read = || -> std::io::Result<Vec<String>> {
+        if path == Path::new("-") {
+            std::io::stdin().lock().lines().collect()
+        } else {
+            BufReader::new(File::open(path)?).lines().collect()
+        }
+    };
+    read().map_err(|error| KeysFromError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+/// Pair each key with the target given by the expression
+fn with_targets(
+    replace_command: &ReplaceCommand,
+    keys: Vec<SourceObject>,
+) -> Vec<(SourceObject, String)> {
+    keys.into_iter()
+        .map(|key| {
+            let target = replace_command.execute(&key.key).into_owned();
+            (key, target)
+        })
+        .collect()
+}
+
+/// Convert a Grantee object to a grant String to use in the CopyObjectRequest
+fn generate_permission_grant(grantee: Grantee) -> Result<String, GranteeParseError> {
+    if let Some(uri) = grantee.uri {
+        return Ok(format!("uri=\"{}\"", uri));
+    }
+    if let Some(id) = grantee.id {
+        return Ok(format!("id=\"{}\"", id));
+    }
+    if let Some(email) = grantee.email_address {
+        return Ok(format!("emailAddress=\"{}\"", email));
+    }
+    Err(GranteeParseError::NoValidID {
+        grantee: Box::new(grantee),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::MappingError;
+    use crate::filter::{FilterAction, KeyFilter};
+    use crate::memory_storage::{MemoryObject, MemoryStorage, StorageCall};
+    use crate::summary::RunSummary;
+    use rusoto_s3::{Grant, Tag};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    const BUCKET: &str = "test-bucket";
+
+    fn source_object() -> MemoryObject {
+        let mut metadata = HashMap::new();
+        metadata.insert(String::from("owner"), String::from("analytics"));
+        MemoryObject {
+            head: HeadObjectOutput {
+                content_length: Some(16),
+                content_type: Some(String::from("text/plain")),
+                e_tag: Some(String::from("\"etag\"")),
+                metadata: Some(metadata),
+                server_side_encryption: Some(String::from("AES256")),
+                ..Default::default()
+            },
+            grants: vec![
+                Grant {
+                    grantee: Some(Grantee {
+                        type_: String::from("CanonicalUser"),
+                        id: Some(String::from("owner-id")),
+                        ..Default::default()
+                    }),
+                    permission: Some(String::from("FULL_CONTROL")),
+                },
+                Grant {
+                    grantee: Some(Grantee {
+                        type_: String::from("Group"),
+                        uri: Some(String::from(
+                            "http://acs.amazonaws.com/groups/global/AllUsers",
+                        )),
+                        ..Default::default()
+                    }),
+                    permission: Some(String::from("READ")),
+                },
+            ],
+            tags: vec![Tag {
+                key: String::from("team"),
+                value: String::from("analytics"),
+            }],
+            body: Vec::new(),
+        }
+    }
+
+    async fn rename(
+        storage: &Arc<MemoryStorage>,
+        expr: &str,
+        edit: impl FnOnce(&mut RenameOptions),
+    ) -> Vec<RenameEvent> {
+        let mut options = RenameOptions::new(expr, BUCKET, None);
+        edit(&mut options);
+        Renamer::new(storage.clone(), options)
+            .unwrap()
+            .run()
+            .map(|x| x.unwrap())
+            .collect()
+            .await
+    }
+
+    fn copy_requests(storage: &MemoryStorage) -> Vec<CopyObjectRequest> {
+        storage
+            .calls()
+            .into_iter()
+            .filter_map(|x| match x {
+                StorageCall::CopyObject(request) => Some(*request),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn preserves_properties_and_acl() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/file.txt", source_object());
+
+        let events = rename(&storage, "s/old/new/", |_| {}).await;
+
+        assert_eq!(storage.keys(BUCKET), vec![String::from("new/file.txt")]);
+        let source = String::from("old/file.txt");
+        let target = String::from("new/file.txt");
+        assert_eq!(
+            events,
+            vec![
+                RenameEvent::Planned {
+                    source: source.clone(),
+                    target: target.clone()
+                },
+                RenameEvent::Copied {
+                    source: source.clone(),
+                    target: target.clone()
+                },
+                RenameEvent::Verified {
+                    source: source.clone(),
+                    target: target.clone()
+                },
+                RenameEvent::Deleted { source, target },
+            ]
+        );
+        let renamed = storage.get_object(BUCKET, "new/file.txt").unwrap();
+        let original = source_object();
+        assert_eq!(renamed.head.metadata, original.head.metadata);
+        assert_eq!(renamed.head.content_type, original.head.content_type);
+        assert_eq!(
+            renamed.head.server_side_encryption,
+            original.head.server_side_encryption
+        );
+        assert_eq!(renamed.grants.len(), 2);
+        for grant in original.grants {
+            assert!(renamed.grants.contains(&grant));
+        }
+
+        let copy = &copy_requests(&storage)[0];
+        assert_eq!(copy.copy_source, "test-bucket/old/file.txt");
+        assert_eq!(copy.metadata_directive.as_deref(), Some("REPLACE"));
+        assert_eq!(copy.acl, None);
+    }
+
+    #[tokio::test]
+    async fn no_preserve_properties_copies_metadata_without_head() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/file.txt", source_object());
+
+        rename(&storage, "s/old/new/", |x| x.no_preserve_properties = true).await;
+
+        assert!(!storage
+            .calls()
+            .iter()
+            .any(|x| x.operation() == "HeadObject" && x.key() == "old/file.txt"));
+        let copy = &copy_requests(&storage)[0];
+        assert_eq!(copy.metadata_directive.as_deref(), Some("COPY"));
+        assert_eq!(copy.server_side_encryption, None);
+        assert!(copy.grant_read.is_some());
+    }
+
+    #[tokio::test]
+    async fn canned_acl_skips_acl_lookup() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/file.txt", source_object());
+
+        rename(&storage, "s/old/new/", |x| {
+            x.canned_acl = Some(CannedACL::BucketOwnerFullControl)
+        })
+        .await;
+
+        assert!(!storage
+            .calls()
+            .iter()
+            .any(|x| matches!(x, StorageCall::GetObjectAcl(_))));
+        let copy = &copy_requests(&storage)[0];
+        assert_eq!(copy.acl.as_deref(), Some("bucket-owner-full-control"));
+        assert_eq!(copy.grant_read, None);
+        assert_eq!(copy.grant_full_control, None);
+    }
+
+    #[tokio::test]
+    async fn no_preserve_acl_sends_no_grants() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/file.txt", source_object());
+
+        rename(&storage, "s/old/new/", |x| x.no_preserve_acl = true).await;
+
+        let copy = &copy_requests(&storage)[0];
+        assert_eq!(copy.grant_read, None);
+        assert_eq!(copy.grant_full_control, None);
+        assert!(storage
+            .get_object(BUCKET, "new/file.txt")
+            .unwrap()
+            .grants
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn no_overwrite_skips_existing_target() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/file.txt", source_object());
+        storage.put_object(BUCKET, "new/file.txt", source_object());
+
+        let events = rename(&storage, "s/old/new/", |x| x.no_overwrite = true).await;
+
+        assert!(copy_requests(&storage).is_empty());
+        assert!(events.contains(&RenameEvent::Skipped {
+            source: String::from("old/file.txt"),
+            target: String::from("new/file.txt"),
+            reason: SkipReason::WouldOverwrite,
+        }));
+        assert_eq!(
+            storage.keys(BUCKET),
+            vec![String::from("new/file.txt"), String::from("old/file.txt")]
+        );
+    }
+
+    #[tokio::test]
+    async fn rename_onto_unchanged_key_is_rejected() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "file.txt", object_of_size(1));
+        storage.put_object(BUCKET, "file1.txt", object_of_size(2));
+
+        let renamer = Renamer::new(storage.clone(), RenameOptions::new("s/\\d//", BUCKET, None));
+        let events: Vec<_> = renamer.unwrap().run().collect().await;
+
+        let error = events.into_iter().find_map(|x| x.err()).unwrap();
+        assert!(error
+            .to_string()
+            .contains("file.txt <- file.txt, file1.txt"));
+        assert!(copy_requests(&storage).is_empty());
+    }
+
+    #[tokio::test]
+    async fn cycle_temporary_key_does_not_overwrite_existing_key() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "12", object_of_size(1));
+        storage.put_object(BUCKET, "21", object_of_size(2));
+        // Not part of the batch, so only found by checking the bucket
+        storage.put_object(BUCKET, "12.s3rename-tmp", object_of_size(3));
+        storage.put_object(BUCKET, "21.s3rename-tmp", object_of_size(4));
+        let options = || {
+            let mut options = RenameOptions::new("s/(\\d)(\\d)/\\2\\1/", BUCKET, None);
+            options.filters = KeyFilters::new(vec![KeyFilter {
+                action: FilterAction::Exclude,
+                pattern: "*.s3rename-tmp".parse().unwrap(),
+            }]);
+            // Checked with a HEAD of each source, but not of the temporary key
+            options.object_filters = ObjectFilters {
+                content_types: vec![String::from("text/plain")],
+                ..Default::default()
+            };
+            options
+        };
+
+        let plan = Renamer::new(storage.clone(), options())
+            .unwrap()
+            .plan()
+            .await
+            .unwrap();
+        assert_eq!(plan.renames.len(), 2);
+        let events: Vec<_> = Renamer::new(storage.clone(), options())
+            .unwrap()
+            .run()
+            .map(|x| x.unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(
+            events
+                .iter()
+                .filter(|x| matches!(x, RenameEvent::Deleted { .. }))
+                .count(),
+            3
+        );
+        let size = |key: &str| storage.get_object(BUCKET, key).unwrap().head.content_length;
+        assert_eq!(
+            storage.keys(BUCKET),
+            vec!["12", "12.s3rename-tmp", "21", "21.s3rename-tmp"]
+        );
+        assert_eq!(
+            (
+                size("12"),
+                size("21"),
+                size("12.s3rename-tmp"),
+                size("21.s3rename-tmp")
+            ),
+            (Some(2), Some(1), Some(3), Some(4))
+        );
+    }
+
+    #[tokio::test]
+    async fn dry_run_makes_no_modifications() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/file.txt", source_object());
+
+        rename(&storage, "s/old/new/", |x| x.dry_run = true).await;
+
+        assert!(storage.calls().iter().all(|x| matches!(
+            x,
+            StorageCall::HeadObject(_) | StorageCall::ListObjectsV2(_)
+        )));
+        assert_eq!(storage.keys(BUCKET), vec![String::from("old/file.txt")]);
+    }
+
+    #[tokio::test]
+    async fn unchanged_key_is_skipped() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/file.txt", source_object());
+
+        let events = rename(&storage, "s/missing/new/", |_| {}).await;
+
+        assert!(storage
+            .calls()
+            .iter()
+            .all(|x| matches!(x, StorageCall::ListObjectsV2(_))));
+        assert_eq!(
+            events,
+            vec![RenameEvent::Skipped {
+                source: String::from("old/file.txt"),
+                target: String::from("old/file.txt"),
+                reason: SkipReason::Unchanged,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn renames_keys_across_listing_pages() {
+        let storage = Arc::new(MemoryStorage::new());
+        for i in 0..1500 {
+            storage.put_object(BUCKET, &format!("old/{:04}", i), source_object());
+        }
+
+        rename(&storage, "s/old/new/", |x| x.no_preserve_acl = true).await;
+
+        let keys = storage.keys(BUCKET);
+        assert_eq!(keys.len(), 1500);
+        assert!(keys.iter().all(|x| x.starts_with("new/")));
+    }
+
+    #[tokio::test]
+    async fn stream_renames_keys_before_listing_completes() {
+        let storage = Arc::new(MemoryStorage::new());
+        for i in 0..1500 {
+            storage.put_object(BUCKET, &format!("old/{:04}", i), source_object());
+        }
+
+        rename(&storage, "s/old/new/", |x| {
+            x.no_preserve_acl = true;
+            x.stream = true;
+            x.jobs = 8;
+        })
+        .await;
+
+        let keys = storage.keys(BUCKET);
+        assert_eq!(keys.len(), 1500);
+        assert!(keys.iter().all(|x| x.starts_with("new/")));
+        let calls = storage.calls();
+        let first_copy = calls
+            .iter()
+            .position(|x| matches!(x, StorageCall::CopyObject(_)))
+            .unwrap();
+        let last_list = calls
+            .iter()
+            .rposition(|x| matches!(x, StorageCall::ListObjectsV2(_)))
+            .unwrap();
+        assert!(first_copy < last_list);
+    }
+
+    #[tokio::test]
+    async fn list_depth_lists_common_prefixes_in_order() {
+        let storage = Arc::new(MemoryStorage::new());
+        let mut keys = vec![
+            String::from("old/2020/01/a.txt"),
+            String::from("old/2020/01/b.txt"),
+            String::from("old/2020/02/a.txt"),
+            String::from("old/2020/index.txt"),
+            String::from("old/2021/01/a.txt"),
+            String::from("old/2021.txt"),
+            String::from("old/readme.txt"),
+        ];
+        // More common prefixes than fit in a single page
+        keys.extend((0..1200).map(|i| format!("old/x/{:04}/file.txt", i)));
+        for key in &keys {
+            storage.put_object(BUCKET, key, source_object());
+        }
+        let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+
+        let mut options = RenameOptions::new("s/^old/new/", BUCKET, Some("old/"));
+        options.no_preserve_acl = true;
+        options.list_depth = 2;
+        options.list_jobs = 4;
+        let journal = Journal::create(&path, &options).unwrap();
+        let _: Vec<_> = Renamer::new(storage.clone(), options)
+            .unwrap()
+            .with_journal(journal)
+            .run()
+            .collect()
+            .await;
+
+        let renamed = storage.keys(BUCKET);
+        assert_eq!(renamed.len(), keys.len());
+        assert!(renamed.iter().all(|x| x.starts_with("new/")));
+        // Keys are still listed in order, so that an interrupted run can be resumed
+        let listed: Vec<String> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .filter_map(|x| match serde_json::from_str(x).unwrap() {
+                JournalEntry::Listed { source, .. } => Some(source),
+                _ => None,
+            })
+            .collect();
+        keys.sort();
+        assert_eq!(listed, keys);
+        let delimited = storage
+            .calls()
+            .iter()
+            .filter(|x| matches!(x, StorageCall::ListObjectsV2(x) if x.delimiter.is_some()))
+            .count();
+        // old/, then old/2020/, old/2021/ and old/x/ (over two pages)
+        assert_eq!(delimited, 5);
+    }
+
+    #[tokio::test]
+    async fn list_depth_fetches_pages_of_each_prefix_as_needed() {
+        let storage = MemoryStorage::new();
+        for i in 0..2500 {
+            storage.put_object(BUCKET, &format!("old/a/{:04}.txt", i), source_object());
+        }
+        storage.put_object(BUCKET, "old/b/file.txt", source_object());
+        let listed = |storage: &MemoryStorage, prefix: &str| {
+            storage
+                .calls()
+                .iter()
+                .filter(|x| matches!(x, StorageCall::ListObjectsV2(x) if x.prefix.as_deref() == Some(prefix)))
+                .count()
+        };
+
+        let mut pages = list_pages(&storage, BUCKET, Some(String::from("old/")), None, 1, 4);
+        let first = pages.try_next().await.unwrap().unwrap();
+        assert_eq!(first.len(), 1000);
+        // The rest of the prefix is only fetched as it is consumed
+        assert_eq!(listed(&storage, "old/a/"), 1);
+
+        let rest: Vec<Vec<SourceObject>> = pages.try_collect().await.unwrap();
+        assert_eq!(
+            rest.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![1000, 500, 1]
+        );
+        assert_eq!(listed(&storage, "old/a/"), 3);
+    }
+
+    #[tokio::test]
+    async fn filters_narrow_renamed_keys_in_order() {
+        let storage = Arc::new(MemoryStorage::new());
+        for key in &[
+            "old/a.jpg",
+            "old/b.png",
+            "old/tmp/c.jpg",
+            "old/tmp/keep.jpg",
+        ] {
+            storage.put_object(BUCKET, key, source_object());
+        }
+        let filter = |action: FilterAction, pattern: &str| KeyFilter {
+            action,
+            pattern: pattern.parse().unwrap(),
+        };
+
+        let events = rename(&storage, "s/old/new/", |options| {
+            options.filters = KeyFilters::new(vec![
+                filter(FilterAction::Include, "*.jpg"),
+                filter(FilterAction::Exclude, "old/tmp/*"),
+                filter(FilterAction::Include, "regex:keep\\.jpg$"),
+            ])
+        })
+        .await;
+
+        assert_eq!(
+            storage.keys(BUCKET),
+            vec![
+                String::from("new/a.jpg"),
+                String::from("new/tmp/keep.jpg"),
+                String::from("old/b.png"),
+                String::from("old/tmp/c.jpg"),
+            ]
+        );
+        assert!(events.iter().all(|x| x.source() != "old/b.png"));
+    }
+
+    #[tokio::test]
+    async fn object_filters_skip_keys_by_size_time_and_storage_class() {
+        let storage = Arc::new(MemoryStorage::new());
+        let object = |size: i64, modified: &str, storage_class: &str| {
+            let mut object = object_of_size(size);
+            object.head.last_modified = Some(String::from(modified));
+            object.head.storage_class = Some(String::from(storage_class));
+            object
+        };
+        storage.put_object(
+            BUCKET,
+            "old/a.txt",
+            object(16, "2021-06-01T00:00:00.000Z", "STANDARD"),
+        );
+        storage.put_object(
+            BUCKET,
+            "old/small.txt",
+            object(8, "2021-06-01T00:00:00.000Z", "STANDARD"),
+        );
+        storage.put_object(
+            BUCKET,
+            "old/earlier.txt",
+            object(16, "2020-12-31T23:59:59.000Z", "STANDARD"),
+        );
+        storage.put_object(
+            BUCKET,
+            "old/glacier.txt",
+            object(16, "2021-06-01T00:00:00.000Z", "GLACIER"),
+        );
+        let filters = ObjectFilters {
+            modified_after: Some(crate::args::parse_time("2021-01-01").unwrap()),
+            min_size: Some(10),
+            storage_classes: vec![String::from("STANDARD")],
+            ..Default::default()
+        };
+
+        let events = rename(&storage, "s/old/new/", |options| {
+            options.object_filters = filters.clone()
+        })
+        .await;
+
+        assert_eq!(
+            storage.keys(BUCKET),
+            vec![
+                String::from("new/a.txt"),
+                String::from("old/earlier.txt"),
+                String::from("old/glacier.txt"),
+                String::from("old/small.txt"),
+            ]
+        );
+        // Listed keys are filtered without being fetched or reported
+        assert_eq!(events.len(), 4);
+        assert!(!storage
+            .calls()
+            .iter()
+            .any(|x| x.operation() == "HeadObject" && x.key().ends_with("small.txt")));
+
+        // Keys which were not listed are filtered once fetched
+        let mut options = RenameOptions::new("s/old/new/", BUCKET, None);
+        options.object_filters = filters;
+        let events: Vec<_> = Renamer::new(storage.clone(), options)
+            .unwrap()
+            .with_keys(vec![String::from("old/small.txt")])
+            .run()
+            .map(|x| x.unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(
+            events,
+            vec![RenameEvent::Skipped {
+                source: String::from("old/small.txt"),
+                target: String::from("new/small.txt"),
+                reason: SkipReason::Filtered,
+            }]
+        );
+        assert!(storage.get_object(BUCKET, "old/small.txt").is_some());
+    }
+
+    #[tokio::test]
+    async fn object_filters_check_tags_metadata_and_content_type() {
+        let storage = Arc::new(MemoryStorage::new());
+        let jpeg = || {
+            let mut object = source_object();
+            object.head.content_type = Some(String::from("image/jpeg"));
+            object
+        };
+        storage.put_object(BUCKET, "old/a.jpg", jpeg());
+        storage.put_object(BUCKET, "old/b.txt", source_object());
+        let mut object = jpeg();
+        object.tags[0].value = String::from("web");
+        storage.put_object(BUCKET, "old/c.jpg", object);
+        let mut object = jpeg();
+        object.head.metadata = None;
+        storage.put_object(BUCKET, "old/d.jpg", object);
+
+        let events = rename(&storage, "s/old/new/", |options| {
+            options.object_filters = ObjectFilters {
+                tags: vec![(String::from("team"), String::from("analytics"))],
+                metadata: vec![(String::from("Owner"), String::from("analytics"))],
+                content_types: vec![String::from("IMAGE/JPEG")],
+                ..Default::default()
+            }
+        })
+        .await;
+
+        assert_eq!(
+            storage.keys(BUCKET),
+            vec![
+                String::from("new/a.jpg"),
+                String::from("old/b.txt"),
+                String::from("old/c.jpg"),
+                String::from("old/d.jpg"),
+            ]
+        );
+        let mut filtered: Vec<&str> = events
+            .iter()
+            .filter_map(|x| match x {
+                RenameEvent::Skipped {
+                    source,
+                    reason: SkipReason::Filtered,
+                    ..
+                } => Some(source.as_str()),
+                _ => None,
+            })
+            .collect();
+        filtered.sort_unstable();
+        assert_eq!(filtered, vec!["old/b.txt", "old/c.jpg", "old/d.jpg"]);
+        // Tags are only fetched for objects which match the other filters
+        let mut tagged: Vec<String> = storage
+            .calls()
+            .iter()
+            .filter(|x| x.operation() == "GetObjectTagging")
+            .map(|x| String::from(x.key()))
+            .collect();
+        tagged.sort_unstable();
+        assert_eq!(tagged, vec!["old/a.jpg", "old/c.jpg"]);
+    }
+
+    #[tokio::test]
+    async fn directory_markers_are_renamed_and_emptied_markers_removed() {
+        let storage = || {
+            let storage = Arc::new(MemoryStorage::new());
+            for key in &[
+                "logs/",
+                "logs/2019/",
+                "logs/2019/a.txt",
+                "logs/2019/empty/",
+                "other/",
+                "other/b.txt",
+            ] {
+                storage.put_object(BUCKET, key, object_of_size(0));
+            }
+            storage
+        };
+        let rename = |storage: &Arc<MemoryStorage>, directory_markers| {
+            let mut options = RenameOptions::new("s/logs/archive/", BUCKET, Some("logs/2019/"));
+            options.directory_markers = directory_markers;
+            options.remove_empty_markers = directory_markers;
+            Renamer::new(storage.clone(), options)
+                .unwrap()
+                .run()
+                .map(|x| x.unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        // Markers are skipped by default
+        let skipped = storage();
+        let events = rename(&skipped, false).await;
+        assert!(events.iter().all(|x| x.source() == "logs/2019/a.txt"));
+        assert!(skipped.get_object(BUCKET, "logs/2019/").is_some());
+
+        let renamed = storage();
+        rename(&renamed, true).await;
+        assert_eq!(
+            renamed.keys(BUCKET),
+            vec![
+                String::from("archive/2019/"),
+                String::from("archive/2019/a.txt"),
+                String::from("archive/2019/empty/"),
+                String::from("other/"),
+                String::from("other/b.txt"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn inventory_replaces_listing() {
+        let storage = Arc::new(MemoryStorage::new());
+        for key in &["old/a.txt", "old/with space.txt", "other/b.txt"] {
+            storage.put_object(BUCKET, key, source_object());
+        }
+        let csv = format!(
+            "{b},old/a.txt,true,16,STANDARD\n\
+             {b},old/a.txt,false,16,STANDARD\n\
+             {b},old/with+space.txt,true,16,GLACIER\n\
+             {b},other/b.txt,true,16,STANDARD\n",
+            b = BUCKET
+        );
+        let mut gzipped = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+        std::io::Write::write_all(&mut gzipped, csv.as_bytes()).unwrap();
+        let data = MemoryObject {
+            body: gzipped.finish().unwrap(),
+            ..Default::default()
+        };
+        storage.put_object("inventory-bucket", "inventory/data/1.csv.gz", data);
+        let manifest = serde_json::json!({
+            "sourceBucket": BUCKET,
+            "destinationBucket": "arn:aws:s3:::inventory-bucket",
+            "fileFormat": "CSV",
+            "fileSchema": "Bucket, Key, IsLatest, Size, StorageClass",
+            "files": [{"key": "inventory/data/1.csv.gz", "size": 100}],
+        });
+        let manifest = MemoryObject {
+            body: manifest.to_string().into_bytes(),
+            ..Default::default()
+        };
+        storage.put_object("inventory-bucket", "inventory/manifest.json", manifest);
+
+        let location = InventoryLocation::S3 {
+            bucket: String::from("inventory-bucket"),
+            key: String::from("inventory/manifest.json"),
+        };
+        let inventory = Inventory::read(storage.clone(), &location).await.unwrap();
+        let mut options = RenameOptions::new("s/old/new/", BUCKET, Some("old/"));
+        options.no_preserve_properties = true;
+        let _: Vec<_> = Renamer::new(storage.clone(), options)
+            .unwrap()
+            .with_inventory(inventory)
+            .run()
+            .collect()
+            .await;
+
+        assert_eq!(
+            storage.keys(BUCKET),
+            vec![
+                String::from("new/a.txt"),
+                String::from("new/with space.txt"),
+                String::from("other/b.txt")
+            ]
+        );
+        assert!(!storage
+            .calls()
+            .iter()
+            .any(|x| matches!(x, StorageCall::ListObjectsV2(_))));
+        // The storage class comes from the inventory, as it would from listing
+        let copies = copy_requests(&storage);
+        assert_eq!(copies.len(), 2);
+        assert_eq!(copies[1].storage_class.as_deref(), Some("GLACIER"));
+    }
+
+    #[tokio::test]
+    async fn inventory_reads_local_parquet_report() {
+        use parquet::data_type::{BoolType, ByteArray, ByteArrayType, Int64Type};
+        use parquet::file::writer::SerializedFileWriter;
+        use parquet::schema::parser::parse_message_type;
+
+        let dir = tempfile::tempdir().unwrap();
+        let schema = parse_message_type(
+            "message s3_inventory {
+                required binary bucket (STRING);
+                required binary key (STRING);
+                optional boolean is_latest;
+                optional int64 size;
+            }",
+        )
+        .unwrap();
+        let file = std::fs::File::create(dir.path().join("1.parquet")).unwrap();
+        let mut writer =
+            SerializedFileWriter::new(file, Arc::new(schema), Default::default()).unwrap();
+        let mut row_group = writer.next_row_group().unwrap();
+        let strings = |values: &[&str]| -> Vec<ByteArray> {
+            values.iter().map(|x| ByteArray::from(*x)).collect()
+        };
+        let mut column = row_group.next_column().unwrap().unwrap();
+        column
+            .typed::<ByteArrayType>()
+            .write_batch(&strings(&[BUCKET, BUCKET]), None, None)
+            .unwrap();
+        column.close().unwrap();
+        let mut column = row_group.next_column().unwrap().unwrap();
+        column
+            .typed::<ByteArrayType>()
+            .write_batch(&strings(&["old/b.txt", "old/a.txt"]), None, None)
+            .unwrap();
+        column.close().unwrap();
+        let mut column = row_group.next_column().unwrap().unwrap();
+        column
+            .typed::<BoolType>()
+            .write_batch(&[true, false], Some(&[1, 1]), None)
+            .unwrap();
+        column.close().unwrap();
+        let mut column = row_group.next_column().unwrap().unwrap();
+        column
+            .typed::<Int64Type>()
+            .write_batch(&[16, 32], Some(&[1, 1]), None)
+            .unwrap();
+        column.close().unwrap();
+        row_group.close().unwrap();
+        writer.close().unwrap();
+        let manifest = serde_json::json!({
+            "sourceBucket": BUCKET,
+            "destinationBucket": "arn:aws:s3:::inventory-bucket",
+            "fileFormat": "Parquet",
+            "files": [{"key": "inventory/data/1.parquet"}],
+        });
+        let path = dir.path().join("manifest.json");
+        std::fs::write(&path, manifest.to_string()).unwrap();
+
+        let storage = Arc::new(MemoryStorage::new());
+        let inventory = Inventory::read(storage, &InventoryLocation::Local(path))
+            .await
+            .unwrap();
+
+        let keys: Vec<SourceObject> = inventory
+            .pages(Some(String::from("old/")), None)
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(
+            keys,
+            vec![SourceObject {
+                key: String::from("old/b.txt"),
+                storage_class: None,
+                size: Some(16),
+                e_tag: None,
+                last_modified: None,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn inventory_pages_are_filtered_while_reading() {
+        let storage = Arc::new(MemoryStorage::new());
+        let mut first: String = (0..1500)
+            .map(|i| format!("{},old/{:04}.txt\n", BUCKET, i))
+            .collect();
+        first.insert_str(0, &format!("{},a.txt\n", BUCKET));
+        let files = [
+            ("inventory/data/1.csv", first),
+            ("inventory/data/2.csv", format!("{},other/a.txt\n", BUCKET)),
+            ("inventory/data/3.csv", format!("{},other/b.txt\n", BUCKET)),
+            (
+                "inventory/data/4.csv",
+                format!("{},b.txt\n{},a.txt\n", BUCKET, BUCKET),
+            ),
+            ("inventory/data/5.csv", format!("{},old/late.txt\n", BUCKET)),
+        ];
+        for (key, csv) in &files {
+            let data = MemoryObject {
+                body: csv.clone().into_bytes(),
+                ..Default::default()
+            };
+            storage.put_object("inventory-bucket", key, data);
+        }
+        let manifest = |keys: &[&str], format: &str| {
+            let files: Vec<_> = keys
+                .iter()
+                .map(|x| serde_json::json!({ "key": x }))
+                .collect();
+            let manifest = serde_json::json!({
+                "sourceBucket": BUCKET,
+                "destinationBucket": "arn:aws:s3:::inventory-bucket",
+                "fileFormat": format,
+                "fileSchema": "Bucket, Key",
+                "files": files,
+            });
+            MemoryObject {
+                body: manifest.to_string().into_bytes(),
+                ..Default::default()
+            }
+        };
+        let manifests = [
+            ("inventory/manifest.json", vec![0, 1, 2], "CSV"),
+            ("unsorted/manifest.json", vec![3], "CSV"),
+            ("late/manifest.json", vec![0, 1, 4], "CSV"),
+            ("orc/manifest.json", vec![], "ORC"),
+        ];
+        for (key, indices, format) in &manifests {
+            let keys: Vec<&str> = indices.iter().map(|&i| files[i].0).collect();
+            storage.put_object("inventory-bucket", key, manifest(&keys, format));
+        }
+        let location = |key: &str| InventoryLocation::S3 {
+            bucket: String::from("inventory-bucket"),
+            key: String::from(key),
+        };
+
+        let inventory = Inventory::read(storage.clone(), &location("inventory/manifest.json"))
+            .await
+            .unwrap();
+        let pages: Vec<Vec<SourceObject>> = inventory
+            .pages(
+                Some(String::from("old/")),
+                Some(String::from("old/0099.txt")),
+            )
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            pages.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![1000, 400]
+        );
+        assert_eq!(pages[0][0].key, "old/0100.txt");
+        // Every file is read, even past the prefix, to check the keys are in order
+        let read: Vec<String> = storage
+            .calls()
+            .iter()
+            .filter(|x| x.operation() == "GetObject")
+            .map(|x| String::from(x.key()))
+            .collect();
+        assert_eq!(
+            read,
+            vec![
+                "inventory/manifest.json",
+                "inventory/data/1.csv",
+                "inventory/data/2.csv",
+                "inventory/data/3.csv"
+            ]
+        );
+
+        // Keys out of order, within a file or in a later file, are not silently skipped
+        for key in &["unsorted/manifest.json", "late/manifest.json"] {
+            let inventory = Inventory::read(storage.clone(), &location(key))
+                .await
+                .unwrap();
+            let result: Result<Vec<_>, _> = inventory
+                .pages(Some(String::from("old/")), None)
+                .try_collect()
+                .await;
+            assert!(matches!(
+                result,
+                Err(InventoryError::InvalidDataFile { .. })
+            ));
+        }
+        assert!(matches!(
+            Inventory::read(storage.clone(), &location("orc/manifest.json")).await,
+            Err(InventoryError::OrcUnsupported)
+        ));
+    }
+
+    #[tokio::test]
+    async fn keys_from_renames_only_given_keys() {
+        let storage = Arc::new(MemoryStorage::new());
+        let mut object = source_object();
+        object.head.storage_class = Some(String::from("STANDARD_IA"));
+        storage.put_object(BUCKET, "old/a.txt", object);
+        storage.put_object(BUCKET, "old/b.txt", source_object());
+        storage.put_object(BUCKET, "old/c.txt", source_object());
+
+        let mut options = RenameOptions::new("s/old/new/", BUCKET, Some("old/"));
+        options.no_preserve_properties = true;
+        let keys = vec!["old/b.txt", "old/a.txt", "other/a.txt", "", "old/a.txt"];
+        let _: Vec<_> = Renamer::new(storage.clone(), options)
+            .unwrap()
+            .with_keys(keys.into_iter().map(String::from).collect())
+            .run()
+            .collect()
+            .await;
+
+        assert_eq!(
+            storage.keys(BUCKET),
+            vec![
+                String::from("new/a.txt"),
+                String::from("new/b.txt"),
+                String::from("old/c.txt")
+            ]
+        );
+        assert!(!storage
+            .calls()
+            .iter()
+            .any(|x| matches!(x, StorageCall::ListObjectsV2(_))));
+        // The storage class is looked up, since it is not known from listing
+        let copies = copy_requests(&storage);
+        assert_eq!(copies[0].storage_class.as_deref(), Some("STANDARD_IA"));
+    }
+
+    #[tokio::test]
+    async fn mapping_file_renames_listed_pairs() {
+        let storage = Arc::new(MemoryStorage::new());
+        for key in &["a.txt", "b.txt", "c.txt", "existing.txt"] {
+            storage.put_object(BUCKET, key, source_object());
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let csv = dir.path().join("mapping.csv");
+        std::fs::write(
+            &csv,
+            "source,target\nb.txt,renamed/b.txt\na.txt,b.txt\nc.txt,existing.txt\n",
+        )
+        .unwrap();
+        let jsonl = dir.path().join("mapping.jsonl");
+        std::fs::write(
+            &jsonl,
+            "{\"source\": \"b.txt\", \"target\": \"renamed/b.txt\"}\n\n\
+             {\"source\": \"a.txt\", \"target\": \"b.txt\"}\n\
+             {\"source\": \"c.txt\", \"target\": \"existing.txt\"}\n",
+        )
+        .unwrap();
+        let renames = read_mapping(&csv).unwrap();
+        assert_eq!(read_mapping(&jsonl).unwrap(), renames);
+
+        let mut options = RenameOptions::new("", BUCKET, None);
+        options.no_overwrite = true;
+        let _: Vec<_> = Renamer::from_mapping(storage.clone(), options, renames)
+            .run()
+            .collect()
+            .await;
+
+        // b.txt is moved out of the way before a.txt is renamed to it, and existing.txt is
+        // not overwritten
+        assert_eq!(
+            storage.keys(BUCKET),
+            vec![
+                String::from("b.txt"),
+                String::from("c.txt"),
+                String::from("existing.txt"),
+                String::from("renamed/b.txt")
+            ]
+        );
+        std::fs::write(&csv, "a.txt,b.txt,c.txt\n").unwrap();
+        assert!(matches!(
+            read_mapping(&csv),
+            Err(MappingError::Parse { line: 1, .. })
+        ));
+        // Unlike a journal, a mapping's last line is not assumed to be cut off by a crash
+        std::fs::write(
+            &jsonl,
+            "{\"source\": \"a.txt\", \"target\": \"b.txt\"}\n\n{\"source\"\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            read_mapping(&jsonl),
+            Err(MappingError::Parse { line: 3, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn large_objects_use_multipart_copy() {
+        let storage = Arc::new(MemoryStorage::new());
+        let mut object = source_object();
+        object.head.content_length = Some(12 * 1024 * 1024);
+        storage.put_object(BUCKET, "old/large.bin", object);
+
+        rename(&storage, "s/old/new/", |x| {
+            x.multipart = MultipartOptions {
+                threshold: 5 * 1024 * 1024,
+                part_size: 5 * 1024 * 1024,
+                parallelism: 2,
+            }
+        })
+        .await;
+
+        assert!(copy_requests(&storage).is_empty());
+        let ranges: Vec<Option<String>> = storage
+            .calls()
+            .into_iter()
+            .filter_map(|x| match x {
+                StorageCall::UploadPartCopy(request) => Some(request.copy_source_range),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ranges.len(), 3);
+        assert!(ranges.contains(&Some(String::from("bytes=10485760-12582911"))));
+        assert!(storage.pending_uploads().is_empty());
+
+        assert_eq!(storage.keys(BUCKET), vec![String::from("new/large.bin")]);
+        let renamed = storage.get_object(BUCKET, "new/large.bin").unwrap();
+        let original = source_object();
+        assert_eq!(renamed.head.content_length, Some(12 * 1024 * 1024));
+        assert_eq!(renamed.head.metadata, original.head.metadata);
+        assert_eq!(
+            renamed.head.server_side_encryption,
+            original.head.server_side_encryption
+        );
+        assert_eq!(renamed.grants.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn multipart_copy_keeps_properties_without_preserving_them() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/large.bin", object_of_size(12 * 1024 * 1024));
+
+        rename(&storage, "s/old/new/", |x| {
+            x.no_preserve_properties = true;
+            x.multipart = MultipartOptions {
+                threshold: 5 * 1024 * 1024,
+                part_size: 5 * 1024 * 1024,
+                parallelism: 2,
+            }
+        })
+        .await;
+
+        assert!(copy_requests(&storage).is_empty());
+        let renamed = storage.get_object(BUCKET, "new/large.bin").unwrap();
+        let original = source_object();
+        assert_eq!(renamed.head.content_type, original.head.content_type);
+        assert_eq!(renamed.head.metadata, original.head.metadata);
+    }
+
+    #[tokio::test]
+    async fn multipart_copy_preserves_tags() {
+        let storage = Arc::new(MemoryStorage::new());
+        let mut object = object_of_size(12 * 1024 * 1024);
+        object.tags.push(Tag {
+            key: String::from("cost centre"),
+            value: String::from("r&d=1/2"),
+        });
+        storage.put_object(BUCKET, "old/large.bin", object.clone());
+
+        rename(&storage, "s/old/new/", |x| {
+            x.multipart.threshold = 5 * 1024 * 1024;
+            x.multipart.part_size = 5 * 1024 * 1024;
+        })
+        .await;
+
+        assert!(copy_requests(&storage).is_empty());
+        let renamed = storage.get_object(BUCKET, "new/large.bin").unwrap();
+        assert_eq!(renamed.tags, object.tags);
+    }
+
+    #[tokio::test]
+    async fn failed_delete_is_reported() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/file.txt", source_object());
+        storage.fail_requests("DeleteObject", "old/file.txt", 1, 403);
+
+        let events = rename(&storage, "s/old/new/", |_| {}).await;
+
+        match events.last() {
+            Some(RenameEvent::Failed {
+                stage,
+                target,
+                request_id,
+                ..
+            }) => {
+                assert_eq!(*stage, RenameStage::Verified);
+                assert_eq!(target.as_deref(), Some("new/file.txt"));
+                // The ID of the failed DeleteObject request is kept for the failure log
+                assert_eq!(request_id.as_ref().map(String::len), Some(16));
+            }
+            other => panic!("Expected failure, got {:?}", other),
+        }
+        assert_eq!(
+            storage.keys(BUCKET),
+            vec![String::from("new/file.txt"), String::from("old/file.txt")]
+        );
+    }
+
+    #[tokio::test]
+    async fn summary_counts_outcome_of_each_key() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "keep/file.txt", source_object());
+        storage.put_object(BUCKET, "old/a.txt", source_object());
+        storage.put_object(BUCKET, "old/b.txt", source_object());
+        storage.fail_requests("DeleteObject", "old/b.txt", 1, 403);
+
+        let events = rename(&storage, "s/old/new/", |_| {}).await;
+
+        let mut summary = RunSummary::default();
+        events.iter().for_each(|x| summary.update(x));
+        assert_eq!(
+            summary,
+            RunSummary {
+                renamed: 1,
+                unchanged: 1,
+                skipped: 0,
+                failed: 1,
+                delete_failed: 1,
+            }
+        );
+        assert!(!summary.all_failed());
+    }
+
+    #[tokio::test]
+    async fn progress_counts_listed_keys_and_copied_bytes() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "keep/file.txt", source_object());
+        storage.put_object(BUCKET, "old/file.txt", source_object());
+
+        let renamer = Renamer::new(
+            storage.clone(),
+            RenameOptions::new("s/old/new/", BUCKET, None),
+        )
+        .unwrap();
+        let progress = renamer.progress();
+        let _: Vec<_> = renamer.run().collect().await;
+
+        assert_eq!(progress.listed(), 2);
+        assert_eq!(progress.bytes_copied(), 16);
+        assert_eq!(progress.requests_in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn throttled_requests_are_retried() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/file.txt", source_object());
+        storage.fail_requests("CopyObject", "new/file.txt", 2, 503);
+        storage.fail_requests("DeleteObject", "old/file.txt", 1, 500);
+
+        let mut options = RenameOptions::new("s/old/new/", BUCKET, None);
+        options.retry.base_delay = Duration::from_millis(1);
+        let renamer = Renamer::new(storage.clone(), options).unwrap();
+        let retries = renamer.retry_counter();
+        let events: Vec<RenameEvent> = renamer.run().map(|x| x.unwrap()).collect().await;
+
+        assert!(matches!(events.last(), Some(RenameEvent::Deleted { .. })));
+        assert_eq!(retries.get(), 3);
+        assert_eq!(storage.keys(BUCKET), vec![String::from("new/file.txt")]);
+    }
+
+    #[tokio::test]
+    async fn requests_are_not_retried_after_max_attempts() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/file.txt", source_object());
+        storage.fail_requests("CopyObject", "new/file.txt", 3, 503);
+
+        let events = rename(&storage, "s/old/new/", |x| {
+            x.retry = RetryPolicy {
+                max_attempts: 3,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(1),
+            }
+        })
+        .await;
+
+        assert!(matches!(
+            events.last(),
+            Some(RenameEvent::Failed {
+                stage: RenameStage::Planned,
+                ..
+            })
+        ));
+        assert_eq!(copy_requests(&storage).len(), 3);
+        assert_eq!(storage.keys(BUCKET), vec![String::from("old/file.txt")]);
+    }
+
+    #[tokio::test]
+    async fn source_is_kept_if_copy_cannot_be_verified() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/file.txt", source_object());
+        // The first HeadObject for the target is the verification
+        storage.fail_requests("HeadObject", "new/file.txt", 1, 404);
+
+        let events = rename(&storage, "s/old/new/", |_| {}).await;
+
+        assert!(matches!(
+            events.last(),
+            Some(RenameEvent::Failed {
+                stage: RenameStage::Copied,
+                ..
+            })
+        ));
+        assert!(!storage
+            .calls()
+            .iter()
+            .any(|x| matches!(x, StorageCall::DeleteObject(_))));
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_new_copies() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/file.txt", source_object());
+
+        let renamer = Renamer::new(
+            storage.clone(),
+            RenameOptions::new("s/old/new/", BUCKET, None),
+        )
+        .unwrap();
+        renamer.shutdown_handle().shutdown();
+        let events: Vec<RenameEvent> = renamer.run().map(|x| x.unwrap()).collect().await;
+
+        assert!(events.is_empty());
+        assert!(copy_requests(&storage).is_empty());
+        assert_eq!(storage.keys(BUCKET), vec![String::from("old/file.txt")]);
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_listing_without_stream() {
+        let storage = Arc::new(MemoryStorage::new());
+        for i in 0..1001 {
+            storage.put_object(BUCKET, &format!("old/{:04}.txt", i), source_object());
+        }
+
+        let renamer = Renamer::new(
+            storage.clone(),
+            RenameOptions::new("s/old/new/", BUCKET, None),
+        )
+        .unwrap();
+        renamer.shutdown_handle().shutdown();
+        let events: Vec<RenameEvent> = renamer.run().map(|x| x.unwrap()).collect().await;
+
+        assert!(events.is_empty());
+        let lists = storage
+            .calls()
+            .iter()
+            .filter(|x| matches!(x, StorageCall::ListObjectsV2(_)))
+            .count();
+        assert_eq!(lists, 1);
+        assert!(copy_requests(&storage).is_empty());
+    }
+
+    #[tokio::test]
+    async fn journal_records_each_stage() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/file.txt", source_object());
+        let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+
+        let options = RenameOptions::new("s/old/new/", BUCKET, None);
+        let journal = Journal::create(&path, &options).unwrap();
+        let _: Vec<_> = Renamer::new(storage.clone(), options)
+            .unwrap()
+            .with_journal(journal)
+            .run()
+            .collect()
+            .await;
+
+        let entries: Vec<JournalEntry> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|x| serde_json::from_str(x).unwrap())
+            .collect();
+        let (source, target) = (String::from("old/file.txt"), String::from("new/file.txt"));
+        assert_eq!(
+            entries[1..].to_vec(),
+            vec![
+                JournalEntry::Listed {
+                    source: source.clone(),
+                    storage_class: None,
+                    size: Some(16),
+                },
+                JournalEntry::ListingComplete,
+                JournalEntry::Copied {
+                    source: source.clone(),
+                    target: target.clone(),
+                },
+                JournalEntry::Verified {
+                    source: source.clone(),
+                    target: target.clone(),
+                },
+                JournalEntry::Deleted { source, target },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn resume_skips_renamed_keys_and_finishes_copied_keys() {
+        let storage = Arc::new(MemoryStorage::new());
+        // old/a.txt was copied but not deleted, old/b.txt was renamed, old/c.txt was not started
+        storage.put_object(BUCKET, "old/a.txt", source_object());
+        storage.put_object(BUCKET, "new/a.txt", source_object());
+        storage.put_object(BUCKET, "new/b.txt", source_object());
+        storage.put_object(BUCKET, "old/c.txt", source_object());
+        let journal = [
+            r#"{"entry":"started","bucket":"test-bucket","prefix":null,"expr":"s/old/new/"}"#,
+            r#"{"entry":"listed","source":"old/a.txt","storage_class":null,"size":16}"#,
+            r#"{"entry":"listed","source":"old/b.txt","storage_class":null,"size":16}"#,
+            r#"{"entry":"listed","source":"old/c.txt","storage_class":null,"size":16}"#,
+            r#"{"entry":"listing_complete"}"#,
+            r#"{"entry":"copied","source":"old/a.txt","target":"new/a.txt"}"#,
+            r#"{"entry":"copied","source":"old/b.txt","target":"new/b.txt"}"#,
+            r#"{"entry":"deleted","source":"old/b.txt","target":"new/b.txt"}"#,
+            r#"{"entry":"copied","source":"old/c.txt","#,
+        ];
+        let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(&path, journal.join("\n")).unwrap();
+
+        let options = RenameOptions::new("s/old/new/", BUCKET, None);
+        let (journal, state) = Journal::resume(&path, &options).unwrap();
+        let events: Vec<RenameEvent> = Renamer::new(storage.clone(), options)
+            .unwrap()
+            .resume_from(journal, state)
+            .run()
+            .map(|x| x.unwrap())
+            .collect()
+            .await;
+
+        assert!(events.contains(&RenameEvent::Deleted {
+            source: String::from("old/a.txt"),
+            target: String::from("new/a.txt"),
+        }));
+        assert!(events.iter().all(|x| x.source() != "old/b.txt"));
+        let copied: Vec<String> = copy_requests(&storage).into_iter().map(|x| x.key).collect();
+        assert_eq!(copied, vec![String::from("new/c.txt")]);
+        assert!(!storage
+            .calls()
+            .iter()
+            .any(|x| matches!(x, StorageCall::ListObjectsV2(_))));
+        assert_eq!(
+            storage.keys(BUCKET),
+            vec![
+                String::from("new/a.txt"),
+                String::from("new/b.txt"),
+                String::from("new/c.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn resume_rejects_journal_for_other_expression() {
+        let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        Journal::create(&path, &RenameOptions::new("s/old/new/", BUCKET, None)).unwrap();
+
+        let options = RenameOptions::new("s/old/other/", BUCKET, None);
+        assert!(Journal::resume(&path, &options).is_err());
+    }
+
+    #[tokio::test]
+    async fn report_records_outcome_of_each_key() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "keep/file.txt", source_object());
+        storage.put_object(BUCKET, "old/file.txt", source_object());
+        let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+
+        let _: Vec<_> = Renamer::new(
+            storage.clone(),
+            RenameOptions::new("s/old/new/", BUCKET, None),
+        )
+        .unwrap()
+        .with_report(Report::create(&path, crate::report::ReportFormat::Csv).unwrap())
+        .run()
+        .collect()
+        .await;
+
+        let mut reader = csv::Reader::from_path(&path).unwrap();
+        let headers = reader.headers().unwrap().clone();
+        assert_eq!(
+            headers.iter().collect::<Vec<_>>(),
+            vec![
+                "source",
+                "target",
+                "action",
+                "error",
+                "bytes",
+                "storage_class",
+                "started_at",
+                "duration_ms"
+            ]
+        );
+        let mut records: Vec<csv::StringRecord> = reader.records().map(|x| x.unwrap()).collect();
+        records.sort_by(|a, b| a[0].cmp(&b[0]));
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0].iter().take(5).collect::<Vec<_>>(),
+            vec![
+                "keep/file.txt",
+                "keep/file.txt",
+                "skipped-unchanged",
+                "key did not change",
+                "16"
+            ]
+        );
+        assert_eq!(
+            records[1].iter().take(5).collect::<Vec<_>>(),
+            vec!["old/file.txt", "new/file.txt", "renamed", "", "16"]
+        );
+        assert!(!records[1][6].is_empty() && !records[1][7].is_empty());
+    }
+
+    #[tokio::test]
+    async fn undo_reverses_renames_from_manifest() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/file.txt", source_object());
+        let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+
+        let _: Vec<_> = Renamer::new(
+            storage.clone(),
+            RenameOptions::new("s/old/new/", BUCKET, None),
+        )
+        .unwrap()
+        .with_manifest(Manifest::create(&path).unwrap())
+        .run()
+        .collect()
+        .await;
+
+        let (bucket, renames) = Manifest::undo_renames(&path).unwrap();
+        assert_eq!(bucket, BUCKET);
+        assert_eq!(
+            renames,
+            vec![PlannedRename {
+                source: String::from("new/file.txt"),
+                target: String::from("old/file.txt"),
+                e_tag: Some(String::from("\"etag\"")),
+            }]
+        );
+
+        let mut options = RenameOptions::new("", &bucket, None);
+        options.no_overwrite = true;
+        let _: Vec<_> = Renamer::from_mapping(storage.clone(), options, renames)
+            .run()
+            .collect()
+            .await;
+
+        assert_eq!(storage.keys(BUCKET), vec![String::from("old/file.txt")]);
+        let restored = storage.get_object(BUCKET, "old/file.txt").unwrap();
+        assert_eq!(restored.head.metadata, source_object().head.metadata);
+        assert_eq!(restored.grants.len(), 2);
+        assert!(source_object()
+            .grants
+            .iter()
+            .all(|x| restored.grants.contains(x)));
+    }
+
+    #[tokio::test]
+    async fn mapping_is_not_renamed_if_etag_changed() {
+        let storage = Arc::new(MemoryStorage::new());
+        let mut changed = source_object();
+        changed.head.e_tag = Some(String::from("\"changed\""));
+        storage.put_object(BUCKET, "new/file.txt", changed);
+
+        let renames = vec![PlannedRename {
+            source: String::from("new/file.txt"),
+            target: String::from("old/file.txt"),
+            e_tag: Some(String::from("\"etag\"")),
+        }];
+        let events: Vec<RenameEvent> = Renamer::from_mapping(
+            storage.clone(),
+            RenameOptions::new("", BUCKET, None),
+            renames,
+        )
+        .run()
+        .map(|x| x.unwrap())
+        .collect()
+        .await;
+
+        assert!(matches!(
+            events.last(),
+            Some(RenameEvent::Failed {
+                stage: RenameStage::Planned,
+                ..
+            })
+        ));
+        assert_eq!(storage.keys(BUCKET), vec![String::from("new/file.txt")]);
+    }
+
+    #[tokio::test]
+    async fn plan_then_apply_keys_from_file() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/a.txt", source_object());
+        storage.put_object(BUCKET, "old/b.txt", source_object());
+
+        let plan = Renamer::new(
+            storage.clone(),
+            RenameOptions::new("s/old/new/", BUCKET, None),
+        )
+        .unwrap()
+        .with_keys(vec![String::from("old/a.txt")])
+        .plan()
+        .await
+        .unwrap();
+        assert_eq!(plan.renames[0].e_tag.as_deref(), Some("\"etag\""));
+
+        plan.check_unchanged(&*storage).await.unwrap();
+        let options = plan
+            .rename_options(&RenameOptions::new("", "", None))
+            .unwrap();
+        let _: Vec<_> = Renamer::from_mapping(storage.clone(), options, plan.renames)
+            .run()
+            .collect()
+            .await;
+
+        assert_eq!(
+            storage.keys(BUCKET),
+            vec![String::from("new/a.txt"), String::from("old/b.txt")]
+        );
+    }
+
+    #[tokio::test]
+    async fn plan_then_apply_renames_planned_keys() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/a.txt", source_object());
+        storage.put_object(BUCKET, "old/b.txt", source_object());
+        storage.put_object(BUCKET, "other.txt", source_object());
+
+        let mut options = RenameOptions::new("s/old/new/", BUCKET, None);
+        options.no_preserve_acl = true;
+        let plan = Renamer::new(storage.clone(), options)
+            .unwrap()
+            .plan()
+            .await
+            .unwrap();
+        assert!(copy_requests(&storage).is_empty());
+        assert!(plan.options.no_preserve_acl);
+        let planned: Vec<(&str, &str)> = plan
+            .renames
+            .iter()
+            .map(|x| (x.source.as_str(), x.target.as_str()))
+            .collect();
+        assert_eq!(
+            planned,
+            vec![("old/a.txt", "new/a.txt"), ("old/b.txt", "new/b.txt")]
+        );
+
+        let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        plan.write(&path).unwrap();
+        let plan = Plan::read(&path).unwrap();
+        plan.check_unchanged(&*storage).await.unwrap();
+        let options = plan
+            .rename_options(&RenameOptions::new("", "", None))
+            .unwrap();
+        assert!(options.no_preserve_acl);
+        let _: Vec<_> = Renamer::from_mapping(storage.clone(), options, plan.renames)
+            .run()
+            .collect()
+            .await;
+
+        assert_eq!(
+            storage.keys(BUCKET),
+            vec![
+                String::from("new/a.txt"),
+                String::from("new/b.txt"),
+                String::from("other.txt"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn apply_refuses_plan_if_source_changed() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/a.txt", source_object());
+        storage.put_object(BUCKET, "old/b.txt", source_object());
+
+        let plan = Renamer::new(
+            storage.clone(),
+            RenameOptions::new("s/old/new/", BUCKET, None),
+        )
+        .unwrap()
+        .plan()
+        .await
+        .unwrap();
+        let mut changed = source_object();
+        changed.head.e_tag = Some(String::from("\"changed\""));
+        storage.put_object(BUCKET, "old/b.txt", changed);
+
+        assert!(plan.check_unchanged(&*storage).await.is_err());
+    }
+
+    fn object_of_size(size: i64) -> MemoryObject {
+        let mut object = source_object();
+        object.head.content_length = Some(size);
+        object
+    }
+
+    fn planned(source: &str, target: &str) -> PlannedRename {
+        PlannedRename {
+            source: String::from(source),
+            target: String::from(target),
+            e_tag: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn colliding_targets_are_rejected() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "file1.txt", source_object());
+        storage.put_object(BUCKET, "file2.txt", source_object());
+
+        let results: Vec<Result<RenameEvent, anyhow::Error>> =
+            Renamer::new(storage.clone(), RenameOptions::new(r"s/\d//", BUCKET, None))
+                .unwrap()
+                .run()
+                .collect()
+                .await;
+
+        assert!(results.last().unwrap().is_err());
+        assert!(copy_requests(&storage).is_empty());
+    }
+
+    #[tokio::test]
+    async fn chains_are_renamed_in_order() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "a", object_of_size(1));
+        storage.put_object(BUCKET, "b", object_of_size(2));
+
+        let renames = vec![planned("a", "b"), planned("b", "c")];
+        let _: Vec<_> = Renamer::from_mapping(
+            storage.clone(),
+            RenameOptions::new("", BUCKET, None),
+            renames,
+        )
+        .run()
+        .collect()
+        .await;
+
+        assert_eq!(
+            storage.keys(BUCKET),
+            vec![String::from("b"), String::from("c")]
+        );
+        let size = |key| storage.get_object(BUCKET, key).unwrap().head.content_length;
+        assert_eq!(size("b"), Some(1));
+        assert_eq!(size("c"), Some(2));
+    }
+
+    #[tokio::test]
+    async fn swaps_are_renamed_through_a_temporary_key() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "a", object_of_size(1));
+        storage.put_object(BUCKET, "b", object_of_size(2));
+
+        let renames = vec![planned("a", "b"), planned("b", "a")];
+        let _: Vec<_> = Renamer::from_mapping(
+            storage.clone(),
+            RenameOptions::new("", BUCKET, None),
+            renames,
+        )
+        .run()
+        .collect()
+        .await;
+
+        assert_eq!(
+            storage.keys(BUCKET),
+            vec![String::from("a"), String::from("b")]
+        );
+        let size = |key| storage.get_object(BUCKET, key).unwrap().head.content_length;
+        assert_eq!(size("a"), Some(2));
+        assert_eq!(size("b"), Some(1));
+    }
+
+    #[tokio::test]
+    async fn jobs_limits_keys_in_flight() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.put_object(BUCKET, "old/a.txt", source_object());
+        storage.put_object(BUCKET, "old/b.txt", source_object());
+
+        rename(&storage, "s/old/new/", |x| x.jobs = 1).await;
+
+        // With one job, each key is renamed completely before the next is started
+        let keys: Vec<String> = storage
+            .calls()
+            .iter()
+            .filter(|x| x.operation() != "ListObjectsV2")
+            .map(|x| String::from(x.key()))
+            .collect();
+        let first_b = keys.iter().position(|x| x.ends_with("b.txt")).unwrap();
+        assert!(keys[first_b..].iter().all(|x| x.ends_with("b.txt")));
+        assert_eq!(
+            storage.keys(BUCKET),
+            vec![String::from("new/a.txt"), String::from("new/b.txt")]
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use super::errors::{ArgumentError, ReportError};
+use super::events::{RenameEvent, SkipReason};
+use super::jsonl::JsonLinesWriter;
+use super::listing::SourceObject;
+use chrono::{DateTime, SecondsFormat, Utc};
+use core::str::FromStr;
+use log::error;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// File format of a run report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// One JSON object per line
+    JsonLines,
+    /// Comma separated values, with a header row
+    Csv,
+}
+
+impl ReportFormat {
+    pub fn possible_strings() -> &'static [&'static str] {
+        &["jsonl", "csv"][..]
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = ArgumentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(ReportFormat::JsonLines),
+            "csv" => Ok(ReportFormat::Csv),
+            _ => Err(ArgumentError::InvalidReportFormat {
+                s: String::from(s),
+                possible_strings: ReportFormat::possible_strings(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for ReportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportFormat::JsonLines => write!(f, "jsonl"),
+            ReportFormat::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+/// Outcome of a key, as recorded in the report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReportAction {
+    Renamed,
+    SkippedUnchanged,
+    SkippedOverwrite,
+    SkippedInterrupted,
+    SkippedAlreadyRenamed,
+    SkippedChainBroken,
+    SkippedFiltered,
+    DryRun,
+    Failed,
+}
+
+impl From<SkipReason> for ReportAction {
+    fn from(reason: SkipReason) -> Self {
+        match reason {
+            SkipReason::Unchanged => ReportAction::SkippedUnchanged,
+            SkipReason::WouldOverwrite => ReportAction::SkippedOverwrite,
+            SkipReason::DryRun => ReportAction::DryRun,
+            SkipReason::Interrupted => ReportAction::SkippedInterrupted,
+            SkipReason::AlreadyRenamed => ReportAction::SkippedAlreadyRenamed,
+            SkipReason::ChainBroken => ReportAction::SkippedChainBroken,
+            SkipReason::Filtered => ReportAction::SkippedFiltered,
+        }
+    }
+}
+
+/// The outcome of a single key, written to the report once the key is finished with
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReportRecord {
+    pub source: String,
+    /// `None` if the key failed before its target was known
+    pub target: Option<String>,
+    pub action: ReportAction,
+    /// Why the key failed, or why it was skipped
+    pub error: Option<String>,
+    /// Object size in bytes, if known
+    pub bytes: Option<i64>,
+    pub storage_class: Option<String>,
+    /// When the rename of the key started (RFC 3339), `None` for keys skipped before starting
+    pub started_at: Option<String>,
+    /// Time taken from starting the key to its outcome
+    pub duration_ms: Option<u64>,
+}
+
+/// A key which has been started but not yet finished with
+struct StartedKey {
+    size: Option<i64>,
+    storage_class: Option<String>,
+    started_at: DateTime<Utc>,
+    started: Instant,
+}
+
+enum ReportWriter {
+    JsonLines(JsonLinesWriter),
+    Csv(Box<Mutex<csv::Writer<File>>>),
+}
+
+/// Machine-readable record of the outcome of every key in a run
+///
+/// Each key is written as a record (a line of JSON, or a CSV row) as soon as it is renamed,
+/// skipped or fails.
+pub struct Report {
+    path: PathBuf,
+    writer: ReportWriter,
+    started: Mutex<HashMap<String, StartedKey>>,
+}
+
+impl Report {
+    /// Create a new report, overwriting any existing file
+    pub fn create(path: &Path, format: ReportFormat) -> Result<Self, ReportError> {
+        let io_error = |error| ReportError::Io {
+            path: path.to_path_buf(),
+            error,
+        };
+        let writer = match format {
+            ReportFormat::JsonLines => {
+                ReportWriter::JsonLines(JsonLinesWriter::create(path).map_err(io_error)?)
+            }
+            ReportFormat::Csv => ReportWriter::Csv(Box::new(Mutex::new(csv::Writer::from_writer(
+                File::create(path).map_err(io_error)?,
+            )))),
+        };
+        Ok(Report {
+            path: path.to_path_buf(),
+            writer,
+            started: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Note that renaming `key` has started, so that its size, storage class and timing are
+    /// included in its record
+    ///
+    /// May be called again with more details of the key (e.g. once its size has been fetched),
+    /// keeping the original start time.
+    pub(crate) fn start(&self, key: &SourceObject) {
+        let mut started = self.started.lock().unwrap();
+        match started.get_mut(&key.key) {
+            Some(entry) => {
+                entry.size = key.size.or(entry.size);
+                entry.storage_class = key.storage_class.clone().or(entry.storage_class.take());
+            }
+            None => {
+                started.insert(
+                    key.key.clone(),
+                    StartedKey {
+                        size: key.size,
+                        storage_class: key.storage_class.clone(),
+                        started_at: Utc::now(),
+                        started: Instant::now(),
+                    },
+                );
+            }
+        }
+    }
+
+    /// Write the record of a key if `event` is its outcome (i.e. it was deleted, skipped or
+    /// failed)
+    ///
+    /// Write errors are logged rather than stopping the run.
+    pub(crate) fn update(&self, event: &RenameEvent) {
+        let (source, target, action, error) = match event {
+            RenameEvent::Planned { .. }
+            | RenameEvent::Copied { .. }
+            | RenameEvent::Verified { .. } => return,
+            RenameEvent::Deleted { source, target } => {
+                (source, Some(target), ReportAction::Renamed, None)
+            }
+            RenameEvent::Skipped {
+                source,
+                target,
+                reason,
+            } => (
+                source,
+                Some(target),
+                ReportAction::from(*reason),
+                Some(reason.to_string()),
+            ),
+            RenameEvent::Failed {
+                source,
+                target,
+                error,
+                ..
+            } => (
+                source,
+                target.as_ref(),
+                ReportAction::Failed,
+                Some(error.clone()),
+            ),
+        };
+        let started = self.started.lock().unwrap().remove(source);
+        let record = ReportRecord {
+            source: source.clone(),
+            target: target.cloned(),
+            action,
+            error,
+            bytes: started.as_ref().and_then(|x| x.size),
+            storage_class: started.as_ref().and_then(|x| x.storage_class.clone()),
+            started_at: started
+                .as_ref()
+                .map(|x| x.started_at.to_rfc3339_opts(SecondsFormat::Millis, true)),
+            duration_ms: started.map(|x| x.started.elapsed().as_millis() as u64),
+        };
+        if let Err(e) = self.write(&record) {
+            error!("Could not write to report {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn write(&self, record: &ReportRecord) -> std::io::Result<()> {
+        match &self.writer {
+            ReportWriter::JsonLines(writer) => writer.write(record),
+            ReportWriter::Csv(writer) => {
+                let mut writer = writer.lock().unwrap();
+                writer.serialize(record)?;
+                writer.flush()
+            }
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use super::errors::request_id;
+use super::progress::Progress;
+use super::storage::Storage;
+use async_trait::async_trait;
+use log::debug;
+use rand::Rng;
+use rusoto_core::RusotoError;
+use rusoto_s3::{AbortMultipartUploadError, AbortMultipartUploadOutput};
+use rusoto_s3::{AbortMultipartUploadRequest, CompleteMultipartUploadError};
+use rusoto_s3::{CompleteMultipartUploadOutput, CompleteMultipartUploadRequest};
+use rusoto_s3::{CopyObjectError, CopyObjectOutput, CopyObjectRequest};
+use rusoto_s3::{CreateMultipartUploadError, CreateMultipartUploadOutput};
+use rusoto_s3::{CreateMultipartUploadRequest, UploadPartCopyError};
+use rusoto_s3::{DeleteObjectError, DeleteObjectOutput, DeleteObjectRequest};
+use rusoto_s3::{GetObjectAclError, GetObjectAclOutput, GetObjectAclRequest};
+use rusoto_s3::{GetObjectError, GetObjectOutput, GetObjectRequest};
+use rusoto_s3::{GetObjectTaggingError, GetObjectTaggingOutput, GetObjectTaggingRequest};
+use rusoto_s3::{HeadObjectError, HeadObjectOutput, HeadObjectRequest};
+use rusoto_s3::{ListObjectsV2Error, ListObjectsV2Output, ListObjectsV2Request};
+use rusoto_s3::{UploadPartCopyOutput, UploadPartCopyRequest};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How failed requests are retried
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts for each request (1 disables retries)
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each further retry
+    pub base_delay: Duration,
+    /// Longest delay between attempts
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(20),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retrying after `attempt` attempts, with "full jitter" (a random delay up to
+    /// the exponential backoff) so that throttled requests do not retry in lockstep
+    fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .checked_mul(1 << attempt.saturating_sub(1).min(31))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        backoff.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+}
+
+/// Number of requests which have been retried during a run
+#[derive(Debug, Clone, Default)]
+pub struct RetryCounter(Arc<AtomicUsize>);
+
+impl RetryCounter {
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn increment(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Whether a request which failed with `error` may succeed if retried, i.e. the error was
+/// throttling (503 SlowDown), any other server error, or a network error
+fn is_retryable<E>(error: &RusotoError<E>) -> bool {
+    match error {
+        RusotoError::HttpDispatch(_) => true,
+        RusotoError::Unknown(response) => {
+            response.status.is_server_error() || response.status.as_u16() == 429
+        }
+        _ => false,
+    }
+}
+
+/// Storage which retries requests which fail with throttling, server or network errors
+///
+/// Every request passes through here, so requests in flight are also counted in `progress`.
+pub struct RetryingStorage {
+    inner: Arc<dyn Storage>,
+    policy: RetryPolicy,
+    retries: RetryCounter,
+    progress: Progress,
+}
+
+impl RetryingStorage {
+    pub fn new(
+        inner: Arc<dyn Storage>,
+        policy: RetryPolicy,
+        retries: RetryCounter,
+        progress: Progress,
+    ) -> Self {
+        RetryingStorage {
+            inner,
+            policy,
+            retries,
+            progress,
+        }
+    }
+
+    async fn retry<T, E, F, Fut>(
+        &self,
+        operation: &str,
+        key: &str,
+        request: F,
+    ) -> Result<T, RusotoError<E>>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, RusotoError<E>>>,
+        E: std::error::Error + 'static,
+    {
+        let mut attempt = 1;
+        loop {
+            let in_flight = self.progress.start_request();
+            let result = request().await;
+            drop(in_flight);
+            match result {
+                Err(error) if attempt < self.policy.max_attempts && is_retryable(&error) => {
+                    let delay = self.policy.delay(attempt);
+                    debug!(
+                        key = key, operation = operation, request_id = request_id(&error);
+                        "Retrying {} for {} in {:?} after attempt {} of {} failed: {}",
+                        operation, key, delay, attempt, self.policy.max_attempts, error
+                    );
+                    self.retries.increment();
+                    tokio::time::delay_for(delay).await;
+                    attempt += 1;
+                }
+                Err(error) => {
+                    debug!(
+                        key = key, operation = operation, request_id = request_id(&error);
+                        "{} for {} failed after {} attempts: {}",
+                        operation, key, attempt, error
+                    );
+                    return Err(error);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for RetryingStorage {
+    async fn list_objects_v2(
+        &self,
+        input: ListObjectsV2Request,
+    ) -> Result<ListObjectsV2Output, RusotoError<ListObjectsV2Error>> {
+        let prefix = input.prefix.clone().unwrap_or_default();
+        self.retry("ListObjectsV2", &prefix, || {
+            self.inner.list_objects_v2(input.clone())
+        })
+        .await
+    }
+
+    async fn head_object(
+        &self,
+        input: HeadObjectRequest,
+    ) -> Result<HeadObjectOutput, RusotoError<HeadObjectError>> {
+        self.retry("HeadObject", &input.key, || {
+            self.inner.head_object(input.clone())
+        })
+        .await
+    }
+
+    async fn get_object_acl(
+        &self,
+        input: GetObjectAclRequest,
+    ) -> Result<GetObjectAclOutput, RusotoError<GetObjectAclError>> {
+        self.retry("GetObjectAcl", &input.key, || {
+            self.inner.get_object_acl(input.clone())
+        })
+        .await
+    }
+
+    async fn get_object_tagging(
+        &self,
+        input: GetObjectTaggingRequest,
+    ) -> Result<GetObjectTaggingOutput, RusotoError<GetObjectTaggingError>> {
+        self.retry("GetObjectTagging", &input.key, || {
+            self.inner.get_object_tagging(input.clone())
+        })
+        .await
+    }
+
+    async fn get_object(
+        &self,
+        input: GetObjectRequest,
+    ) -> Result<GetObjectOutput, RusotoError<GetObjectError>> {
+        self.retry("GetObject", &input.key, || {
+            self.inner.get_object(input.clone())
+        })
+        .await
+    }
+
+    async fn copy_object(
+        &self,
+        input: CopyObjectRequest,
+    ) -> Result<CopyObjectOutput, RusotoError<CopyObjectError>> {
+        self.retry("CopyObject", &input.key, || {
+            self.inner.copy_object(input.clone())
+        })
+        .await
+    }
+
+    async fn delete_object(
+        &self,
+        input: DeleteObjectRequest,
+    ) -> Result<DeleteObjectOutput, RusotoError<DeleteObjectError>> {
+        self.retry("DeleteObject", &input.key, || {
+            self.inner.delete_object(input.clone())
+        })
+        .await
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        input: CreateMultipartUploadRequest,
+    ) -> Result<CreateMultipartUploadOutput, RusotoError<CreateMultipartUploadError>> {
+        self.retry("CreateMultipartUpload", &input.key, || {
+            self.inner.create_multipart_upload(input.clone())
+        })
+        .await
+    }
+
+    async fn upload_part_copy(
+        &self,
+        input: UploadPartCopyRequest,
+    ) -> Result<UploadPartCopyOutput, RusotoError<UploadPartCopyError>> {
+        self.retry("UploadPartCopy", &input.key, || {
+            self.inner.upload_part_copy(input.clone())
+        })
+        .await
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        input: CompleteMultipartUploadRequest,
+    ) -> Result<CompleteMultipartUploadOutput, RusotoError<CompleteMultipartUploadError>> {
+        self.retry("CompleteMultipartUpload", &input.key, || {
+            self.inner.complete_multipart_upload(input.clone())
+        })
+        .await
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        input: AbortMultipartUploadRequest,
+    ) -> Result<AbortMultipartUploadOutput, RusotoError<AbortMultipartUploadError>> {
+        self.retry("AbortMultipartUpload", &input.key, || {
+            self.inner.abort_multipart_upload(input.clone())
+        })
+        .await
+    }
+}
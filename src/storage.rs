@@ -0,0 +1,158 @@
+use async_trait::async_trait;
+use rusoto_core::RusotoError;
+use rusoto_s3::{AbortMultipartUploadError, AbortMultipartUploadOutput};
+use rusoto_s3::{AbortMultipartUploadRequest, CompleteMultipartUploadError};
+use rusoto_s3::{CompleteMultipartUploadOutput, CompleteMultipartUploadRequest};
+use rusoto_s3::{CopyObjectError, CopyObjectOutput, CopyObjectRequest};
+use rusoto_s3::{CreateMultipartUploadError, CreateMultipartUploadOutput};
+use rusoto_s3::{CreateMultipartUploadRequest, UploadPartCopyError};
+use rusoto_s3::{DeleteObjectError, DeleteObjectOutput, DeleteObjectRequest};
+use rusoto_s3::{GetObjectAclError, GetObjectAclOutput, GetObjectAclRequest};
+use rusoto_s3::{GetObjectError, GetObjectOutput, GetObjectRequest};
+use rusoto_s3::{GetObjectTaggingError, GetObjectTaggingOutput, GetObjectTaggingRequest};
+use rusoto_s3::{HeadObjectError, HeadObjectOutput, HeadObjectRequest};
+use rusoto_s3::{ListObjectsV2Error, ListObjectsV2Output, ListObjectsV2Request};
+use rusoto_s3::{S3Client, S3};
+use rusoto_s3::{UploadPartCopyOutput, UploadPartCopyRequest};
+
+/// The subset of S3 operations used to rename keys.
+///
+/// This is implemented for `S3Client`, and for `MemoryStorage` so that the rename logic can be
+/// run without access to AWS.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn list_objects_v2(
+        &self,
+        input: ListObjectsV2Request,
+    ) -> Result<ListObjectsV2Output, RusotoError<ListObjectsV2Error>>;
+
+    async fn head_object(
+        &self,
+        input: HeadObjectRequest,
+    ) -> Result<HeadObjectOutput, RusotoError<HeadObjectError>>;
+
+    async fn get_object_acl(
+        &self,
+        input: GetObjectAclRequest,
+    ) -> Result<GetObjectAclOutput, RusotoError<GetObjectAclError>>;
+
+    async fn get_object_tagging(
+        &self,
+        input: GetObjectTaggingRequest,
+    ) -> Result<GetObjectTaggingOutput, RusotoError<GetObjectTaggingError>>;
+
+    async fn get_object(
+        &self,
+        input: GetObjectRequest,
+    ) -> Result<GetObjectOutput, RusotoError<GetObjectError>>;
+
+    async fn copy_object(
+        &self,
+        input: CopyObjectRequest,
+    ) -> Result<CopyObjectOutput, RusotoError<CopyObjectError>>;
+
+    async fn delete_object(
+        &self,
+        input: DeleteObjectRequest,
+    ) -> Result<DeleteObjectOutput, RusotoError<DeleteObjectError>>;
+
+    async fn create_multipart_upload(
+        &self,
+        input: CreateMultipartUploadRequest,
+    ) -> Result<CreateMultipartUploadOutput, RusotoError<CreateMultipartUploadError>>;
+
+    async fn upload_part_copy(
+        &self,
+        input: UploadPartCopyRequest,
+    ) -> Result<UploadPartCopyOutput, RusotoError<UploadPartCopyError>>;
+
+    async fn complete_multipart_upload(
+        &self,
+        input: CompleteMultipartUploadRequest,
+    ) -> Result<CompleteMultipartUploadOutput, RusotoError<CompleteMultipartUploadError>>;
+
+    async fn abort_multipart_upload(
+        &self,
+        input: AbortMultipartUploadRequest,
+    ) -> Result<AbortMultipartUploadOutput, RusotoError<AbortMultipartUploadError>>;
+}
+
+#[async_trait]
+impl Storage for S3Client {
+    async fn list_objects_v2(
+        &self,
+        input: ListObjectsV2Request,
+    ) -> Result<ListObjectsV2Output, RusotoError<ListObjectsV2Error>> {
+        S3::list_objects_v2(self, input).await
+    }
+
+    async fn head_object(
+        &self,
+        input: HeadObjectRequest,
+    ) -> Result<HeadObjectOutput, RusotoError<HeadObjectError>> {
+        S3::head_object(self, input).await
+    }
+
+    async fn get_object_acl(
+        &self,
+        input: GetObjectAclRequest,
+    ) -> Result<GetObjectAclOutput, RusotoError<GetObjectAclError>> {
+        S3::get_object_acl(self, input).await
+    }
+
+    async fn get_object_tagging(
+        &self,
+        input: GetObjectTaggingRequest,
+    ) -> Result<GetObjectTaggingOutput, RusotoError<GetObjectTaggingError>> {
+        S3::get_object_tagging(self, input).await
+    }
+
+    async fn get_object(
+        &self,
+        input: GetObjectRequest,
+    ) -> Result<GetObjectOutput, RusotoError<GetObjectError>> {
+        S3::get_object(self, input).await
+    }
+
+    async fn copy_object(
+        &self,
+        input: CopyObjectRequest,
+    ) -> Result<CopyObjectOutput, RusotoError<CopyObjectError>> {
+        S3::copy_object(self, input).await
+    }
+
+    async fn delete_object(
+        &self,
+        input: DeleteObjectRequest,
+    ) -> Result<DeleteObjectOutput, RusotoError<DeleteObjectError>> {
+        S3::delete_object(self, input).await
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        input: CreateMultipartUploadRequest,
+    ) -> Result<CreateMultipartUploadOutput, RusotoError<CreateMultipartUploadError>> {
+        S3::create_multipart_upload(self, input).await
+    }
+
+    async fn upload_part_copy(
+        &self,
+        input: UploadPartCopyRequest,
+    ) -> Result<UploadPartCopyOutput, RusotoError<UploadPartCopyError>> {
+        S3::upload_part_copy(self, input).await
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        input: CompleteMultipartUploadRequest,
+    ) -> Result<CompleteMultipartUploadOutput, RusotoError<CompleteMultipartUploadError>> {
+        S3::complete_multipart_upload(self, input).await
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        input: AbortMultipartUploadRequest,
+    ) -> Result<AbortMultipartUploadOutput, RusotoError<AbortMultipartUploadError>> {
+        S3::abort_multipart_upload(self, input).await
+    }
+}
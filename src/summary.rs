@@ -0,0 +1,67 @@
+use super::events::{RenameEvent, RenameStage, SkipReason};
+use std::fmt;
+
+/// Counts of the outcome of every key in a run, built up from its events
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunSummary {
+    /// Keys which were copied and had their source deleted
+    pub renamed: usize,
+    /// Keys which the expression did not change
+    pub unchanged: usize,
+    /// Keys which were skipped for any other reason (e.g. --no-overwrite or a dry run)
+    pub skipped: usize,
+    /// Keys whose rename failed, including `delete_failed`
+    pub failed: usize,
+    /// Keys which were copied but whose source could not be deleted, so exist at both keys
+    pub delete_failed: usize,
+}
+
+impl RunSummary {
+    pub fn update(&mut self, event: &RenameEvent) {
+        match event {
+            RenameEvent::Deleted { .. } => self.renamed += 1,
+            RenameEvent::Skipped {
+                reason: SkipReason::Unchanged,
+                ..
+            } => self.unchanged += 1,
+            RenameEvent::Skipped { .. } => self.skipped += 1,
+            RenameEvent::Failed { stage, .. } => {
+                self.failed += 1;
+                if *stage == RenameStage::Verified {
+                    self.delete_failed += 1;
+                }
+            }
+            RenameEvent::Planned { .. }
+            | RenameEvent::Copied { .. }
+            | RenameEvent::Verified { .. } => {}
+        }
+    }
+
+    /// Number of keys which have been renamed, skipped or failed
+    pub fn processed(&self) -> usize {
+        self.renamed + self.unchanged + self.skipped + self.failed
+    }
+
+    /// Whether every key which was attempted failed, i.e. some failed and none were renamed
+    pub fn all_failed(&self) -> bool {
+        self.failed > 0 && self.renamed == 0
+    }
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Renamed {} keys, {} unchanged, {} skipped, {} failed",
+            self.renamed, self.unchanged, self.skipped, self.failed
+        )?;
+        if self.delete_failed > 0 {
+            write!(
+                f,
+                " ({} copied but could not be deleted)",
+                self.delete_failed
+            )?;
+        }
+        Ok(())
+    }
+}
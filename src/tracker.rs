@@ -0,0 +1,82 @@
+use super::events::{RenameEvent, RenameStage};
+use std::collections::HashMap;
+
+/// A key which was left in an intermediate state
+#[derive(Debug, Clone, PartialEq)]
+pub struct IncompleteKey {
+    pub source: String,
+    pub target: String,
+    /// Last stage the key reached
+    pub stage: RenameStage,
+}
+
+/// Tracks the stage of each key from the events of a run
+///
+/// Used to report exactly which keys were left in an intermediate state (i.e. possibly existing
+/// at both the source and target) when a run fails or is interrupted.
+#[derive(Debug, Default)]
+pub struct StageTracker {
+    /// Keys which have been planned but not yet deleted or failed, by source key
+    in_progress: HashMap<String, (String, RenameStage)>,
+    /// Keys which failed after being copied
+    failed_after_copy: Vec<IncompleteKey>,
+}
+
+impl StageTracker {
+    pub fn update(&mut self, event: &RenameEvent) {
+        match event {
+            RenameEvent::Planned { source, target } => {
+                self.in_progress
+                    .insert(source.clone(), (target.clone(), RenameStage::Planned));
+            }
+            RenameEvent::Copied { source, target } => {
+                self.in_progress
+                    .insert(source.clone(), (target.clone(), RenameStage::Copied));
+            }
+            RenameEvent::Verified { source, target } => {
+                self.in_progress
+                    .insert(source.clone(), (target.clone(), RenameStage::Verified));
+            }
+            RenameEvent::Skipped { source, .. } | RenameEvent::Deleted { source, .. } => {
+                self.in_progress.remove(source);
+            }
+            RenameEvent::Failed {
+                source,
+                target,
+                stage,
+                ..
+            } => {
+                self.in_progress.remove(source);
+                if let (Some(target), RenameStage::Copied) | (Some(target), RenameStage::Verified) =
+                    (target, stage)
+                {
+                    self.failed_after_copy.push(IncompleteKey {
+                        source: source.clone(),
+                        target: target.clone(),
+                        stage: *stage,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Keys which failed after being copied, so exist at both the source and target
+    pub fn failed_after_copy(&self) -> &[IncompleteKey] {
+        &self.failed_after_copy
+    }
+
+    /// Keys which are still being renamed (at the Planned stage a copy may be in progress)
+    pub fn in_progress(&self) -> Vec<IncompleteKey> {
+        let mut keys: Vec<IncompleteKey> = self
+            .in_progress
+            .iter()
+            .map(|(source, (target, stage))| IncompleteKey {
+                source: source.clone(),
+                target: target.clone(),
+                stage: *stage,
+            })
+            .collect();
+        keys.sort_by(|a, b| a.source.cmp(&b.source));
+        keys
+    }
+}